@@ -1,209 +1,1171 @@
-pub(crate) mod util;
+pub mod bvh;
+pub mod camera;
+pub mod environment;
+pub mod error;
+pub mod film;
+pub mod filter;
+pub mod integrator;
+pub mod light;
+pub mod material;
+pub mod shape;
+pub mod util;
+pub mod output;
+pub mod pass;
+pub mod progressive;
+pub mod stats;
+mod obj;
+mod scene;
 
-const INV256: f64 = 1.0 / 256.0;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use image::{Rgb, RgbImage};
-use util::{Camera, Color, Vector3, World};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use error::Result;
+use film::{Film, ToneSettings};
+use pass::{Pass, PassSample};
+use progressive::{Accumulation, CheckpointSettings, PixelSum};
+use stats::{Progress, ProgressObserver, RayCounts, RenderStats};
+use filter::{Filter, Sampling};
+use integrator::Integrator;
+use material::Material;
+use camera::Camera;
+use util::{Color, Vector3, World};
 
-fn triangle_collision(
+// closest shape hit by the ray within tmin..tmax as (t, u, v, shape),
+// t is -1 when nothing is hit. children are visited front to back and
+// skipped once they start behind the closest hit found so far
+fn shape_collision(
     origin: &Vector3,
     norm: &Vector3,
     world: &World,
-    boxi: usize,
+    tmin: f64,
+    tmax: f64
 ) -> (f64, f64, f64, isize) {
-    let bbox = &world.bbox[boxi];
-    let left = bbox.left;
-    let right = bbox.right;
-
-    let ldata: (f64, f64, f64, isize) = {
-        if left == -1 {
-            let (t, u, v) = world.triangles[boxi].intersect(&origin, &norm);
-            (t, u, v, boxi as isize)
-        } else {
-            let lbbox = &world.bbox[left as usize];
-            if lbbox.intersect(origin, norm) {
-                triangle_collision(&origin, &norm, &world, left as usize)
-            } else {
-                (-1.0f64, 0.0f64, 0.0f64, 0isize)
+    let norm_inv = norm.inverse();
+    let mut data = (-1.0f64, 0.0f64, 0.0f64, 0isize);
+    let mut closest = tmax;
+    let mut visits = 0;
+    let mut tests = 0;
+
+    //a world of only lights and environment has no tree
+    let mut stack = Vec::<(usize, f64)>::with_capacity(64);
+    let root = world.bbox.first().and_then(|root| root.intersect(origin, &norm_inv, tmin, closest));
+    if let Some(t) = root {
+        stack.push((0, t));
+    }
+
+    while let Some((boxi, entry)) = stack.pop() {
+        if entry > closest {
+            continue;
+        }
+
+        visits += 1;
+        let bbox = &world.bbox[boxi];
+        if bbox.left == -1 {
+            tests += bbox.count as u64;
+            for i in bbox.start..bbox.start + bbox.count {
+                if let Some((t, u, v)) = world.shapes[i].intersect(origin, norm) {
+                    if t >= tmin && t < closest {
+                        closest = t;
+                        data = (t, u, v, i as isize);
+                    }
+                }
             }
+            continue;
         }
-    };
 
-    let rdata: (f64, f64, f64, isize) = {
-        if right == -1 {
-            let (t, u, v) = world.triangles[boxi].intersect(&origin, &norm);
-            (t, u, v, boxi as isize)
-        } else {
-            let rbbox = &world.bbox[right as usize];
-            if rbbox.intersect(origin, norm) {
-                triangle_collision(&origin, &norm, &world, right as usize)
-            } else {
-                (-1.0f64, 0.0f64, 0.0f64, 0isize)
+        let left = bbox.left as usize;
+        let right = bbox.right as usize;
+        let lhit = world.bbox[left].intersect(origin, &norm_inv, tmin, closest);
+        let rhit = world.bbox[right].intersect(origin, &norm_inv, tmin, closest);
+
+        //the nearer child is pushed last so it is visited first
+        match (lhit, rhit) {
+            (Some(lt), Some(rt)) => {
+                if lt <= rt {
+                    stack.push((right, rt));
+                    stack.push((left, lt));
+                } else {
+                    stack.push((left, lt));
+                    stack.push((right, rt));
+                }
+            },
+            (Some(lt), None) => stack.push((left, lt)),
+            (None, Some(rt)) => stack.push((right, rt)),
+            (None, None) => {}
+        }
+    }
+
+    stats::count(|c| {
+        c.node_visits += visits;
+        c.shape_tests += tests;
+    });
+    return data;
+}
+
+// fraction of light that makes it through everything along the ray within
+// tmin..tmax. every hit lets 1 - opacity of it through and the first
+// opaque hit stops the search
+fn shadow_transmittance(
+    origin: &Vector3,
+    norm: &Vector3,
+    world: &World,
+    tmin: f64,
+    tmax: f64
+) -> f64 {
+    stats::count(|c| c.shadow_rays += 1);
+    for light in world.lights.iter() {
+        if let Some((t, _)) = light.intersect(origin, norm) {
+            if t >= tmin && t < tmax {
+                return 0.0;
             }
         }
-    };
+    }
 
-    if ldata.0 == -1.0 {
-        return rdata;
+    let norm_inv = norm.inverse();
+    let mut transmittance = 1.0;
+    let mut visits = 0;
+    let mut tests = 0;
+
+    let mut stack = Vec::<usize>::with_capacity(64);
+    let root = world.bbox.first().and_then(|root| root.intersect(origin, &norm_inv, tmin, tmax));
+    if root.is_some() {
+        stack.push(0);
     }
 
-    if rdata.0 == -1.0 {
-        return ldata;
+    'search: while let Some(boxi) = stack.pop() {
+        visits += 1;
+        let bbox = &world.bbox[boxi];
+        if bbox.left == -1 {
+            for i in bbox.start..bbox.start + bbox.count {
+                tests += 1;
+                if let Some((t, u, v)) = world.shapes[i].intersect(origin, norm) {
+                    if t >= tmin && t < tmax {
+                        transmittance *= 1.0 - world.obtain_opacity(i, u, v);
+                        if transmittance <= 0.0 {
+                            transmittance = 0.0;
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+
+        for child in [bbox.left as usize, bbox.right as usize] {
+            if world.bbox[child].intersect(origin, &norm_inv, tmin, tmax).is_some() {
+                stack.push(child);
+            }
+        }
     }
 
-    return if ldata.0 < rdata.0 { ldata } else { rdata };
+    stats::count(|c| {
+        c.node_visits += visits;
+        c.shape_tests += tests;
+    });
+    return transmittance;
 }
 
-fn ray_trace(pos: &Vector3, ray: &Vector3, world: &World, reflect_times: usize) -> Color {
-    let data = triangle_collision(pos, ray, world, world.bbox.len() - 1);
+// radiance of the closest light hit by the ray up to tmax, if any. the
+// sun is hit at infinity
+fn light_collision(origin: &Vector3, norm: &Vector3, world: &World, tmax: f64) -> Option<Color> {
+    let mut closest = tmax;
+    let mut radiance = None;
+    for light in world.lights.iter() {
+        if let Some((t, emitted)) = light.intersect(origin, norm) {
+            if t <= closest {
+                closest = t;
+                radiance = Some(emitted);
+            }
+        }
+    }
 
-    if data.0 != -1.0 {
-        let triangle = &world.triangles[data.3 as usize];
-        let mut point_pos = triangle.obtain_pos(data.1, data.2);
-        
-        let mut norm_dot = triangle.norm.dot(&ray);
-        let norm = if norm_dot <= 0.0 {
-            triangle.norm
-        } else {
-            norm_dot = triangle.norm_opp.dot(&ray);
-            triangle.norm_opp
-        };
-        
-        let norm_rot_x = f64::atan2(norm.x, norm.z);
-        let norm_rot_y = f64::asin(norm.y);
-
-        //point light
-        let mut lum = Color::new_zero();
-        for point in world.point_lights.iter() {
-
-            let mut light_dis = point_pos.sub(&point.pos);
-            let dis2 = light_dis.normalize_dis();
-            let (tri_dis, _, _, _) =
-                triangle_collision(&point.pos, &light_dis, &world, world.bbox.len() - 1);
-
-            //make sure no triangles are blocking
-            if !(tri_dis != -1.0 && tri_dis * tri_dis < dis2 - 0.001) {
-                let inst = point.lumen / (dis2.abs());
-                lum.r += inst * point.color.r;
-                lum.g += inst * point.color.g;
-                lum.b += inst * point.color.b;
-                lum.a += inst * point.color.a;
+    return radiance;
+}
+
+// what a ray that leaves the scene sees, with an alpha of 0
+fn background(ray: &Vector3, world: &World) -> Color {
+    let mut color = match &world.environment {
+        Some(environment) => environment.radiance(ray),
+        None => Color::new_zero()
+    };
+    color.a = 0.0;
+    return color;
+}
+
+// sends samples shadow rays to every light and the environment from pos,
+// just one to the sun without a size and spots, and hands the direction
+// and the light that gets through to f, already divided by the number of
+// samples
+fn sample_lights<F: FnMut(&Vector3, &Color)>(world: &World, pos: &Vector3, samples: usize,
+                                              rng: &mut SmallRng, mut f: F) {
+    for light in world.lights.iter() {
+        let samples = if light.is_delta() { 1 } else { samples };
+        let inv = 1.0 / samples as f64;
+        for _ in 0..samples {
+            let sample = match light.sample(pos, rng) {
+                Some(sample) => sample,
+                None => continue
+            };
+
+            let transmittance = shadow_transmittance(pos, &sample.dir, world,
+                                                     0.0, sample.dist - 0.001);
+            if transmittance <= 0.0 {
+                continue;
             }
 
-            //-90 degrees in radians
-            let mut rot_x = -1.5708f64;
-            let mut rot_y = -1.5708f64;
-            if reflect_times < 1 {
-                let mut luminosity = Color::new_zero();
-                while rot_x <= 1.6 {
-                    while rot_y <= 1.6 {
-                        let mut direction = Vector3::new(
-                            norm_rot_x + rot_x,
-                            norm_rot_y + rot_y,
-                            0.0
-                        );
-                        direction.normalize();
-                        
-                        let reflect_color = ray_trace(&point_pos, &direction,
-                                                      world, reflect_times + 1);
-                        lum.r = f64::max(lum.r, 0.9 * reflect_color.r);
-                        lum.g = f64::max(lum.g, 0.9 * reflect_color.g);
-                        lum.b = f64::max(lum.b, 0.9 * reflect_color.b);
-                        lum.a = f64::max(lum.a, 0.9 * reflect_color.a);
-                        
-                        rot_y += 0.25;
-                    }
-                    rot_x += 0.25;
+            let w = transmittance * inv;
+            f(&sample.dir, &Color::new(sample.weight.r * w, sample.weight.g * w,
+                                       sample.weight.b * w, sample.weight.a * w));
+        }
+    }
+    if let Some(environment) = &world.environment {
+        let inv = 1.0 / samples as f64;
+        for _ in 0..samples {
+            let sample = match environment.sample(rng) {
+                Some(sample) => sample,
+                None => continue
+            };
+
+            let transmittance = shadow_transmittance(pos, &sample.dir, world,
+                                                     0.0, f64::INFINITY);
+            if transmittance <= 0.0 {
+                continue;
+            }
+
+            let w = transmittance * inv;
+            f(&sample.dir, &Color::new(sample.weight.r * w, sample.weight.g * w,
+                                       sample.weight.b * w, sample.weight.a * w));
+        }
+    }
+}
+
+// light from every light that reaches pos and leaves
+// towards wo, norm is the outward shading normal and surface the color
+// at pos
+fn direct_light(world: &World, pos: &Vector3, norm: &Vector3, wo: &Vector3,
+                material: &Material, surface: &Color, samples: usize,
+                rng: &mut SmallRng) -> Color {
+    let mut lum = Color::new_zero();
+    for point in world.point_lights.iter() {
+        let mut light_dir = point.pos.sub(pos);
+        let dis2 = light_dir.normalize_dis();
+
+        let f = material.eval(surface, norm, wo, &light_dir);
+        if f.r <= 0.0 && f.g <= 0.0 && f.b <= 0.0 {
+            continue;
+        }
+
+        let transmittance = shadow_transmittance(pos, &light_dir, world,
+                                                 0.0, dis2.sqrt() - 0.001);
+        if transmittance <= 0.0 {
+            continue;
+        }
+
+        let inst = transmittance * point.lumen / dis2;
+        lum.r += inst * f.r * point.color.r;
+        lum.g += inst * f.g * point.color.g;
+        lum.b += inst * f.b * point.color.b;
+    }
+
+    sample_lights(world, pos, samples, rng, |dir, light| {
+        let f = material.eval(surface, norm, wo, dir);
+        lum.r += f.r * light.r;
+        lum.g += f.g * light.g;
+        lum.b += f.b * light.b;
+    });
+
+    return lum;
+}
+
+// shades a hit on a shape with a material: direct light through the bsdf
+// and one sampled bounce while there is depth left. returns the color and
+// the part of it that is direct light
+fn shade_material(ray: &Vector3, world: &World, data: (f64, f64, f64, isize),
+                  reflect_times: usize, max_depth: usize,
+                  rng: &mut SmallRng) -> (Color, Color) {
+    let shape = &world.shapes[data.3 as usize];
+    let material = &world.materials[shape.material() as usize];
+    let hit = shape.interaction(data.1, data.2);
+
+    //outward shading normal
+    let mut norm = hit.norm;
+    if norm.dot(&hit.face_norm) < 0.0 {
+        norm = norm.scale(-1.0);
+    }
+
+    let surface = world.obtain_color(data.3 as usize, data.1, data.2);
+    let wo = ray.scale(-1.0);
+
+    let mut color = direct_light(world, &hit.pos, &norm, &wo, material, &surface,
+                                 world.light_samples, rng);
+    color.a = world.obtain_opacity(data.3 as usize, data.1, data.2);
+    let direct = color;
+
+    if reflect_times < max_depth {
+        if let Some(sample) = material.sample(&surface, &norm, &wo, rng) {
+            let bounce = ray_trace(&hit.pos, &sample.dir, world,
+                                   reflect_times + 1, max_depth, sample.delta, rng);
+            color.r += sample.weight.r * bounce.r;
+            color.g += sample.weight.g * bounce.g;
+            color.b += sample.weight.b * bounce.b;
+        }
+    }
+
+    return (color, direct);
+}
+
+// shades a hit on a shape without a material from its color and reflect,
+// returns the color and the part of it that is not reflected
+fn shade_surface(ray: &Vector3, world: &World, data: (f64, f64, f64, isize),
+                 reflect_times: usize, max_depth: usize,
+                 rng: &mut SmallRng) -> (Color, Color) {
+    let shape = &world.shapes[data.3 as usize];
+    let hit = shape.interaction(data.1, data.2);
+    let point_pos = hit.pos;
+    
+    let mut face_norm = hit.face_norm;
+    if face_norm.dot(ray) > 0.0 {
+        face_norm = face_norm.scale(-1.0);
+    }
+
+    //shading normal on the same side as the face
+    let mut norm = hit.norm;
+    if norm.dot(&face_norm) < 0.0 {
+        norm = Vector3::new(-norm.x, -norm.y, -norm.z);
+    }
+    let norm_dot = norm.dot(ray);
+    
+    let norm_rot_x = f64::atan2(norm.x, norm.z);
+    let norm_rot_y = f64::asin(norm.y);
+
+    //point light
+    let mut lum = Color::new_zero();
+    for point in world.point_lights.iter() {
+
+        let mut light_dis = point_pos.sub(&point.pos);
+        let dis2 = light_dis.normalize_dis();
+
+        //how much of the light gets past whatever is in the way
+        let transmittance = shadow_transmittance(&point.pos, &light_dis, world,
+                                                 0.0, (dis2 - 0.001).max(0.0).sqrt());
+        if transmittance > 0.0 {
+            let inst = transmittance * point.lumen / (dis2.abs());
+            lum.r += inst * point.color.r;
+            lum.g += inst * point.color.g;
+            lum.b += inst * point.color.b;
+            lum.a += inst * point.color.a;
+        }
+
+        //-90 degrees in radians
+        let mut rot_x = -std::f64::consts::FRAC_PI_2;
+        let mut rot_y = -std::f64::consts::FRAC_PI_2;
+        if reflect_times < 1 {
+            while rot_x <= 1.6 {
+                while rot_y <= 1.6 {
+                    let mut direction = Vector3::new(
+                        norm_rot_x + rot_x,
+                        norm_rot_y + rot_y,
+                        0.0
+                    );
+                    direction.normalize();
+                    
+                    let reflect_color = ray_trace(&point_pos, &direction, world,
+                                                  reflect_times + 1, max_depth, true, rng);
+                    lum.r = f64::max(lum.r, 0.9 * reflect_color.r);
+                    lum.g = f64::max(lum.g, 0.9 * reflect_color.g);
+                    lum.b = f64::max(lum.b, 0.9 * reflect_color.b);
+                    lum.a = f64::max(lum.a, 0.9 * reflect_color.a);
+                    
+                    rot_y += 0.25;
                 }
+                rot_x += 0.25;
             }
         }
+    }
+
+    //the other lights on the side the ray came from, through a lambertian
+    //brdf like the path tracer so they match between the integrators
+    sample_lights(world, &point_pos, world.light_samples, rng, |dir, light| {
+        let cos = norm.dot(dir);
+        if cos <= 0.0 || dir.dot(&face_norm) <= 0.0 {
+            return;
+        }
+
+        let inst = cos / std::f64::consts::PI;
+        lum.r += light.r * inst;
+        lum.g += light.g * inst;
+        lum.b += light.b * inst;
+    });
+    
+    //the alpha is how much of the pixel the surface covers, not light
+    let opacity = world.obtain_opacity(data.3 as usize, data.1, data.2);
+    let mut color = world.obtain_color(data.3 as usize, data.1, data.2);
+    color.mul_self(lum.r, lum.g, lum.b, 1.0);
+    color.a = opacity;
+    let mut direct = color;
+    
+    //reflection
+    let reflection = shape.reflect(data.1, data.2);
+    if reflection != 0.0 && reflect_times < max_depth {
+        let dot_val = 2.0 * norm_dot;
+        let reflection_dir = Vector3::new(
+            ray.x - dot_val * norm.x,
+            ray.y - dot_val * norm.y,
+            ray.z - dot_val * norm.z,
+        );
         
-        let mut color = world.obtain_color(data.3 as usize, data.1, data.2);
-        color.mul_self(lum.r, lum.g, lum.b, lum.a);
+        let reflect_color = ray_trace(&point_pos, &reflection_dir, world,
+                                      reflect_times + 1, max_depth, true, rng);
         
-        //reflection
-        let reflection = triangle.obtain_reflect(data.1, data.2);
-        if reflection != 0.0 {
-            if reflect_times < 5 {
-                let dot_val = 2.0 * norm_dot;
-                let reflection_dir = Vector3::new(
-                    ray.x - dot_val * norm.x,
-                    ray.y - dot_val * norm.y,
-                    ray.z - dot_val * norm.z,
-                );
-                
-                let reflect_color = ray_trace(&point_pos, &reflection_dir,
-                                              world, reflect_times + 1);
-                
-                let reflect_opp = 1.0 - reflection;
-                color.r = reflect_opp * color.r + reflection * reflect_color.r;
-                color.g = reflect_opp * color.g + reflection * reflect_color.g;
-                color.b = reflect_opp * color.b + reflection * reflect_color.b;
-                color.a = reflect_opp * color.a + reflection * reflect_color.a;
-            }
+        let reflect_opp = 1.0 - reflection;
+        color.r = reflect_opp * color.r + reflection * reflect_color.r;
+        color.g = reflect_opp * color.g + reflection * reflect_color.g;
+        color.b = reflect_opp * color.b + reflection * reflect_color.b;
+        direct.mul_self(reflect_opp, reflect_opp, reflect_opp, 1.0);
+    }
+
+    return (color, direct);
+}
+
+// where a ray goes on after passing through the transparent part of the
+// shape it hit, bent by the ior of its material if it has one
+fn pass_direction(ray: &Vector3, world: &World, data: (f64, f64, f64, isize)) -> Vector3 {
+    let shape = &world.shapes[data.3 as usize];
+    if shape.material() == -1 {
+        return *ray;
+    }
+
+    let hit = shape.interaction(data.1, data.2);
+    let mut norm = hit.norm;
+    if norm.dot(&hit.face_norm) < 0.0 {
+        norm = norm.scale(-1.0);
+    }
+    return world.materials[shape.material() as usize].pass_direction(&norm, ray);
+}
+
+// traces a reflected ray, the camera rays go through trace
+fn ray_trace(pos: &Vector3, ray: &Vector3, world: &World,
+             reflect_times: usize, max_depth: usize, specular: bool,
+             rng: &mut SmallRng) -> Color {
+    stats::count(|c| c.reflection_rays += 1);
+    return trace(pos, ray, world, reflect_times, max_depth, specular, rng).0;
+}
+
+// ray_trace that also returns the part of the color that is direct light,
+// see pass::Pass::Direct. specular is false after a bounce off a bsdf
+// that is not a perfect mirror or window, the lights and environment it
+// runs into were already sampled directly at the point it came from
+fn trace(pos: &Vector3, ray: &Vector3, world: &World,
+         reflect_times: usize, max_depth: usize, specular: bool,
+         rng: &mut SmallRng) -> (Color, Color) {
+    return trace_through(pos, ray, world, reflect_times, max_depth, specular, 0, rng);
+}
+
+// how many transparent surfaces one ray may pass before it stops
+const MAX_PASSES: usize = 64;
+
+fn trace_through(pos: &Vector3, ray: &Vector3, world: &World,
+                 reflect_times: usize, max_depth: usize, specular: bool,
+                 passes: usize, rng: &mut SmallRng) -> (Color, Color) {
+    let data = shape_collision(pos, ray, world, 0.0, f64::INFINITY);
+    let tmax = if data.0 == -1.0 { f64::INFINITY } else { data.0 };
+    if let Some(mut radiance) = light_collision(pos, ray, world, tmax) {
+        if !specular {
+            radiance = Color::new_zero();
+        }
+        radiance.a = 1.0;
+        return (radiance, radiance);
+    }
+    if data.0 == -1.0 {
+        //the environment is sampled directly like the lights
+        let sky = if specular { background(ray, world) } else { Color::new_zero() };
+        return (sky, sky);
+    }
+
+    let shape = &world.shapes[data.3 as usize];
+    let opacity = world.obtain_opacity(data.3 as usize, data.1, data.2);
+
+    let (mut color, mut direct) = if opacity <= 0.0 {
+        (Color::new_zero(), Color::new_zero())
+    } else if shape.material() != -1 {
+        shade_material(ray, world, data, reflect_times, max_depth, rng)
+    } else {
+        shade_surface(ray, world, data, reflect_times, max_depth, rng)
+    };
+
+    //the rest is whatever is behind the surface. a straight pass does not
+    //count as a bounce, a refracted one does
+    if opacity < 1.0 {
+        let dir = pass_direction(ray, world, data);
+        let bent = dir.sub(ray).dot(&dir.sub(ray)) > 1e-12;
+        if (bent && reflect_times >= max_depth) || passes >= MAX_PASSES {
+            return (color, direct);
         }
 
-        return color;
+        let hit = shape.interaction(data.1, data.2);
+        let next = if bent { reflect_times + 1 } else { reflect_times };
+        stats::count(|c| c.reflection_rays += 1);
+        let (behind, behind_direct) = trace_through(&hit.pos, &dir, world, next, max_depth,
+                                                    specular, passes + 1, rng);
+
+        let clear = 1.0 - opacity;
+        color.r = opacity * color.r + clear * behind.r;
+        color.g = opacity * color.g + clear * behind.g;
+        color.b = opacity * color.b + clear * behind.b;
+        color.a = opacity + clear * behind.a;
+        direct.r = opacity * direct.r + clear * behind_direct.r;
+        direct.g = opacity * direct.g + clear * behind_direct.g;
+        direct.b = opacity * direct.b + clear * behind_direct.b;
     }
-    
-    return Color::new_zero();
+
+    return (color, direct);
+}
+
+// region of the image to render, in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl Crop {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        return Crop { x, y, width, height };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    // render only part of the frame, none renders the whole image
+    pub crop: Option<Crop>,
+    // worker threads, 0 uses every available core
+    pub threads: usize,
+    // width and height of the square tiles handed to the workers
+    pub tile_size: u32,
+    // rays per pixel, a single sample goes through the pixel center
+    pub samples: usize,
+    pub sampling: Sampling,
+    // how the samples of a pixel are weighted together
+    pub filter: Filter,
+    // changes the random sample positions
+    pub seed: u64,
+    pub integrator: Integrator,
+    // maximum number of reflection bounces
+    pub max_depth: usize,
+    // extra images to render next to the beauty pass
+    pub passes: Vec<Pass>,
+    // samples per pixel of one round over the whole image, the image
+    // gets better round after round until all samples are taken. none
+    // takes every sample in a single round
+    pub progressive: Option<usize>,
+    // the sums of all samples are written here after every round
+    pub checkpoint: Option<PathBuf>,
+    // carry on from the checkpoint file when there is one
+    pub resume: bool,
+    // how previews are tone mapped
+    pub tone: ToneSettings,
+    // the image is saved here after every round and while a round
+    // renders, at most once per preview_interval
+    pub preview: Option<PathBuf>,
+    pub preview_interval: Duration
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        return Self {
+            crop: None,
+            threads: 0,
+            tile_size: 32,
+            samples: 1,
+            sampling: Sampling::Stratified,
+            filter: Filter::new_box(),
+            seed: 0,
+            integrator: Integrator::Legacy,
+            max_depth: 5,
+            passes: Vec::<Pass>::new(),
+            progressive: None,
+            checkpoint: None,
+            resume: false,
+            tone: ToneSettings::new(),
+            preview: None,
+            preview_interval: Duration::from_secs(10)
+        };
+    }
+
+    fn thread_count(&self) -> usize {
+        if self.threads != 0 {
+            return self.threads;
+        }
+
+        return thread::available_parallelism().map_or(1, |n| n.get());
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// per frame constants used to turn a pixel into a ray
+struct Screen {
+    cam: Camera,
+    aspect: f64,
+    half_width: f64,
+    half_height: f64,
+    samples: usize,
+    sampling: Sampling,
+    filter: Filter,
+    seed: u64,
+    integrator: Integrator,
+    max_depth: usize,
+    passes: Vec<Pass>
+}
+
+// what the camera ray sees first for the render passes, lights and
+// fully transparent surfaces included
+fn pass_sample(origin: &Vector3, ray: &Vector3, world: &World) -> PassSample {
+    let mut sample = PassSample::new_miss();
+    stats::count(|c| c.pass_rays += 1);
+    let data = stats::uncounted(|| shape_collision(origin, ray, world, 0.0, f64::INFINITY));
+    if data.0 == -1.0 {
+        return sample;
+    }
+
+    let id = data.3 as usize;
+    let shape = &world.shapes[id];
+    let hit = shape.interaction(data.1, data.2);
+
+    let mut norm = hit.norm;
+    if norm.dot(ray) > 0.0 {
+        norm = norm.scale(-1.0);
+    }
+
+    let mut albedo = world.obtain_albedo(id, data.1, data.2);
+    if shape.material() != -1 {
+        let color = world.materials[shape.material() as usize].color();
+        albedo.mul_self(color.r, color.g, color.b, 1.0);
+    }
+
+    sample.depth = hit.pos.sub(origin).distance2().sqrt();
+    sample.normal = norm;
+    sample.albedo = albedo;
+    sample.uv = shape.uv(data.1, data.2);
+    sample.shape = world.shape_ids[id] as isize;
+    sample.texture = shape.texture();
+    return sample;
+}
+
+// where sample i of count goes in the filter footprint, scaled to the
+// unit square
+fn footprint_position(sampling: Sampling, i: usize, count: usize,
+                      rng: &mut SmallRng) -> (f64, f64) {
+    if sampling == Sampling::Jittered {
+        return (rng.gen::<f64>(), rng.gen::<f64>());
+    }
+
+    //a partial last row spreads its samples over the whole width
+    //so no part of the footprint is left without one
+    let cols = (count as f64).sqrt().ceil() as usize;
+    let rows = count.div_ceil(cols);
+    let row = i / cols;
+    let row_cols = if row == rows - 1 { count - row * cols } else { cols };
+    return (((i % cols) as f64 + rng.gen::<f64>()) / row_cols as f64,
+            (row as f64 + rng.gen::<f64>()) / rows as f64);
 }
 
-pub fn raytracer(image: &mut RgbImage, cam: &Camera, world: &mut World) {
-    let mut ray = Vector3::new_zero();
-    let mut pos = Vector3::new_zero();
-    pos.set(&cam.pos);
+impl Screen {
+    // takes count more samples of the pixel for the given round
+    fn render_pixel(&self, x: u32, y: u32, world: &World, round: u32, count: usize) -> PixelSum {
+        let cx = x as f64 + 0.5;
+        let cy = y as f64 + 0.5;
+        let mut sum = PixelSum::new(self.passes.len());
+
+        //every pixel has its own sequence so tiles can run in any order,
+        //and every round its own so they do not repeat each other
+        let mut rng = SmallRng::seed_from_u64(
+            (((y as u64) << 32 | x as u64) ^ self.seed.wrapping_mul(0x9E3779B97F4A7C15))
+                .wrapping_add((round as u64).wrapping_mul(0xD1B54A32D192ED03)));
+
+        if self.samples == 1 {
+            let (color, sample) = self.render_sample(cx, cy, &mut rng, world);
+            sum.add(&self.passes, 1.0, 0.0, &color, &sample);
+            return sum;
+        }
+
+        // the samples are spread over the filter footprint
+        let radius = self.filter.radius();
+        for i in 0..count {
+            let (fx, fy) = footprint_position(self.sampling, i, count, &mut rng);
+            let dx = (2.0 * fx - 1.0) * radius;
+            let dy = (2.0 * fy - 1.0) * radius;
+            let weight = self.filter.weight(dx, dy);
+            let (color, sample) = self.render_sample(cx + dx, cy + dy, &mut rng, world);
+            sum.add(&self.passes, weight, dx * dx + dy * dy, &color, &sample);
+        }
+
+        return sum;
+    }
+
+    // sx and sy are in pixels, measured from the top left of the image
+    fn render_sample(&self, sx: f64, sy: f64, rng: &mut SmallRng,
+                     world: &World) -> (Color, PassSample) {
+        // get ray through the point in screen
+        let lens = (rng.gen(), rng.gen());
+        let ray = self.cam.ray(
+            (sx - self.half_width) / self.half_width,
+            (self.half_height - sy) / self.half_height,
+            self.aspect,
+            lens);
 
-    let fov = 0.5 * cam.fov;
+        let (origin, ray) = match ray {
+            Some(ray) => ray,
+            None => return (Color::new_zero(), PassSample::new_miss())
+        };
+        stats::count(|c| c.primary_rays += 1);
 
-    let sinz = cam.rot.z.to_radians().sin();
-    let cosz = cam.rot.z.to_radians().cos();
+        let (color, direct) = match self.integrator {
+            Integrator::Legacy => trace(&origin, &ray, world, 0, self.max_depth, true, rng),
+            Integrator::PathTracer => integrator::path_trace(&origin, &ray, world, self.max_depth, rng)
+        };
+
+        let mut sample = if self.passes.is_empty() {
+            PassSample::new_miss()
+        } else {
+            pass_sample(&origin, &ray, world)
+        };
+        sample.direct = direct;
+        return (color, sample);
+    }
+}
+
+// the film size decides the resolution, cam.projection how it maps to rays.
+// the frame is split into tiles which are traced in parallel, every pixel
+// is traced independently so the output does not depend on the thread count.
+// the film gets the linear radiance, see Film::to_image for 8 bit output
+pub fn raytracer(film: &mut Film, cam: &Camera, world: &mut World,
+                 settings: &RenderSettings) -> Result<RenderStats> {
+    return raytracer_with_progress(film, cam, world, settings, &mut |_: &Progress| {});
+}
+
+// keeps count of a render for the progress observer and the stats
+struct Tracker<'a> {
+    observer: &'a mut dyn ProgressObserver,
+    progress: Progress,
+    counts: RayCounts,
+    start: Instant,
+    // time spent writing previews and checkpoints
+    saving: Duration,
+    last_preview: Instant
+}
+
+impl Tracker<'_> {
+    fn tile_done(&mut self, samples: u64, counts: &RayCounts) {
+        self.counts.add(counts);
+        self.progress.done += samples;
+        self.progress.rays = self.counts.rays();
+        self.progress.elapsed = self.start.elapsed();
+        self.observer.progress(&self.progress);
+    }
+
+    fn save<F: FnOnce() -> Result<()>>(&mut self, f: F) -> Result<()> {
+        let timer = Instant::now();
+        let result = f();
+        self.saving += timer.elapsed();
+        return result;
+    }
+
+    fn save_preview(&mut self, settings: &RenderSettings, film: &Film) -> Result<()> {
+        self.last_preview = Instant::now();
+        return match &settings.preview {
+            Some(preview) => self.save(|| output::save(preview, film, &settings.tone)),
+            None => Ok(())
+        };
+    }
+}
+
+// raytracer that tells the observer how far it is after every finished tile
+pub fn raytracer_with_progress(film: &mut Film, cam: &Camera, world: &mut World,
+                               settings: &RenderSettings,
+                               observer: &mut dyn ProgressObserver) -> Result<RenderStats> {
+    let mut stats = RenderStats::default();
+    cam.validate()?;
 
     if !world.validated {
-        world.compute();
+        let timer = Instant::now();
+        world.compute()?;
+        stats.add_phase("setup", timer.elapsed());
+    }
+
+    //the world is only read from here on
+    let world: &World = world;
+
+    let width = film.width();
+    let height = film.height();
+
+    let screen = Screen {
+        cam: cam.clone(),
+        aspect: width as f64 / height as f64,
+        half_width: 0.5 * width as f64,
+        half_height: 0.5 * height as f64,
+        samples: settings.samples.max(1),
+        sampling: settings.sampling,
+        filter: settings.filter,
+        seed: settings.seed,
+        integrator: settings.integrator,
+        max_depth: settings.max_depth,
+        passes: settings.passes.clone()
+    };
+    film.set_passes(&settings.passes)?;
+
+    let (x_start, y_start, x_end, y_end) = match settings.crop {
+        Some(crop) => (
+            crop.x.min(width),
+            crop.y.min(height),
+            crop.x.saturating_add(crop.width).min(width),
+            crop.y.saturating_add(crop.height).min(height)),
+        None => (0, 0, width, height)
+    };
+
+    let tile_size = settings.tile_size.max(1);
+    let mut tiles = Vec::<Crop>::new();
+    for y in (y_start..y_end).step_by(tile_size as usize) {
+        for x in (x_start..x_end).step_by(tile_size as usize) {
+            tiles.push(Crop::new(x, y,
+                                 tile_size.min(x_end - x),
+                                 tile_size.min(y_end - y)));
+        }
+    }
+
+    let threads = settings.thread_count().min(tiles.len());
+    let samples = screen.samples;
+    let round_samples = settings.progressive.unwrap_or(samples).clamp(1, samples);
+
+    let checkpoint_settings = CheckpointSettings {
+        crop: Crop::new(x_start, y_start, x_end - x_start, y_end - y_start),
+        seed: settings.seed,
+        filter: settings.filter,
+        sampling: settings.sampling,
+        integrator: settings.integrator,
+        max_depth: settings.max_depth,
+        round_samples
+    };
+    let mut accumulation = match &settings.checkpoint {
+        Some(path) if settings.resume && path.exists() => {
+            let accumulation = Accumulation::load(path, width, height, &settings.passes,
+                                                  checkpoint_settings)?;
+            //start from what the checkpoint already has
+            accumulation.write_film(film);
+            accumulation
+        },
+        _ => Accumulation::new(width, height, &settings.passes, checkpoint_settings)?
+    };
+
+    let pixels = tiles.iter().map(|t| t.width as u64 * t.height as u64).sum::<u64>();
+    let resumed = pixels * accumulation.samples() as u64;
+    let rounds_left = samples.saturating_sub(accumulation.samples() as usize).div_ceil(round_samples);
+    let mut tracker = Tracker {
+        observer,
+        progress: Progress {
+            done: resumed,
+            total: pixels * samples as u64,
+            round: accumulation.rounds(),
+            rounds: accumulation.rounds() + rounds_left as u32,
+            elapsed: Duration::ZERO,
+            rays: 0,
+            resumed
+        },
+        counts: RayCounts::default(),
+        start: Instant::now(),
+        saving: Duration::ZERO,
+        last_preview: Instant::now()
+    };
+
+    while (accumulation.samples() as usize) < samples {
+        let round = accumulation.rounds();
+        let count = round_samples.min(samples - accumulation.samples() as usize);
+        tracker.progress.round = round + 1;
+        render_round(film, &mut accumulation, &screen, world, &tiles, threads,
+                     round, count, settings, &mut tracker)?;
+        accumulation.finish_round(count);
+
+        if let Some(path) = &settings.checkpoint {
+            tracker.save(|| accumulation.save(path))?;
+        }
+        if (accumulation.samples() as usize) < samples {
+            tracker.save_preview(settings, film)?;
+        }
     }
 
-    if world.triangles.len() == 0 {
-        return;
+    stats.counts = tracker.counts;
+    stats.add_phase("render", tracker.start.elapsed().saturating_sub(tracker.saving));
+    stats.add_phase("save", tracker.saving);
+    return Ok(stats);
+}
+
+// takes count samples of every pixel of the tiles and adds them to the
+// accumulation and the film
+fn render_round(film: &mut Film, accumulation: &mut Accumulation, screen: &Screen, world: &World,
+                tiles: &[Crop], threads: usize, round: u32, count: usize,
+                settings: &RenderSettings, tracker: &mut Tracker) -> Result<()> {
+    let next_tile = AtomicUsize::new(0);
+    //set when the main thread gives up, the receiver lives until the
+    //scope ends so the workers would otherwise render every tile first
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<(Crop, Vec<PixelSum>, RayCounts)>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let next_tile = &next_tile;
+            let stop = &stop;
+
+            scope.spawn(move || loop {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let i = next_tile.fetch_add(1, Ordering::Relaxed);
+                if i >= tiles.len() {
+                    break;
+                }
+
+                let tile = tiles[i];
+                let mut pixels = Vec::<PixelSum>::with_capacity(
+                    tile.width as usize * tile.height as usize);
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        pixels.push(screen.render_pixel(x, y, world, round, count));
+                    }
+                }
+
+                if sender.send((tile, pixels, stats::take_counts())).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        //the finished tiles are written on this thread
+        for (tile, pixels, counts) in receiver.iter() {
+            for (i, sum) in pixels.iter().enumerate() {
+                let x = tile.x + i as u32 % tile.width;
+                let y = tile.y + i as u32 / tile.width;
+                accumulation.add_pixel(x, y, sum);
+                accumulation.write_pixel(film, x, y);
+            }
+            tracker.tile_done(pixels.len() as u64 * count as u64, &counts);
+
+            if tracker.last_preview.elapsed() >= settings.preview_interval {
+                let result = tracker.save_preview(settings, film);
+                if result.is_err() {
+                    stop.store(true, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
+
+        return Ok(());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Projection;
+    use environment::Environment;
+    use error::Error;
+    use light::Light;
+    use util::Vector2;
+
+    // a 0.5 grey diffuse floor under a small quad light
+    fn lit_floor() -> World {
+        let mut world = World::new();
+        let grey = world.create_material(Material::new_diffuse(Color::new(0.5, 0.5, 0.5, 1.0)));
+        let white = Color::new(1.0, 1.0, 1.0, 1.0);
+        let uv = Vector2::new(0.0, 0.0);
+        world.create_plane(Vector3::new(-50.0, 0.0, -50.0), Vector3::new(-50.0, 0.0, 50.0),
+                           Vector3::new(50.0, 0.0, -50.0), Vector3::new(50.0, 0.0, 50.0),
+                           uv, uv, uv, uv, white, white, white, white, [0.0; 4], -1, grey);
+        world.create_light(Light::new_quad(Vector3::new(-2.0, 10.0, -2.0),
+                                           Vector3::new(2.0, 10.0, -2.0),
+                                           Vector3::new(-2.0, 10.0, 2.0), white, 2000.0));
+        return world;
     }
 
-    for x in 400..512i32 {
-        for y in 0..512i32 {
-            // get ray and pos point in screen
-            ray.x = ((x - 256) as f64 - 0.5) * INV256;
-            ray.y = ((256 - y) as f64 - 0.5) * INV256;
+    // mean red of a small render of the floor
+    fn mean(world: &mut World, integrator: Integrator) -> f64 {
+        let cam = Camera::look_at(Vector3::new(0.0, 5.0, -20.0), Vector3::new(0.0, 0.0, 0.0),
+                                  Vector3::new(0.0, 1.0, 0.0), 40.0).unwrap();
+        let mut settings = RenderSettings::new();
+        settings.threads = 1;
+        settings.samples = 64;
+        settings.integrator = integrator;
+        let mut film = Film::new(8, 8).unwrap();
+        raytracer(&mut film, &cam, world, &settings).unwrap();
 
-            // cam.rotate ray to z
-            let ray_x = ray.x * cosz - ray.y * sinz;
-            let ray_y = ray.x * sinz + ray.y * cosz;
-            ray.x = ray_x;
-            ray.y = ray_y;
+        let mut sum = 0.0;
+        for y in 0..8 {
+            for x in 0..8 {
+                sum += film.pixel(x, y).r;
+            }
+        }
+        return sum / 64.0;
+    }
 
-            // fov
-            ray.x = ray.x * fov + cam.rot.x;
-            ray.y = ray.y * fov + cam.rot.y;
+    #[test]
+    fn transparent_stacks_end() {
+        let mut world = lit_floor();
+        let clear = Color::new(1.0, 1.0, 1.0, 0.0);
+        let uv = Vector2::new(0.0, 0.0);
+        for i in 1..4000 {
+            let y = i as f64 * 0.001;
+            world.create_plane(Vector3::new(-50.0, y, -50.0), Vector3::new(-50.0, y, 50.0),
+                               Vector3::new(50.0, y, -50.0), Vector3::new(50.0, y, 50.0),
+                               uv, uv, uv, uv, clear, clear, clear, clear, [0.0; 4], -1, -1);
+        }
+        assert!(mean(&mut world, Integrator::Legacy).is_finite());
+    }
+
+    fn assert_integrators_agree(world: &mut World) {
+        let legacy = mean(world, Integrator::Legacy);
+        let path = mean(world, Integrator::PathTracer);
+        assert!(path > 0.0);
+        assert!((legacy - path).abs() < 0.05 * path, "legacy {legacy}, path {path}");
+    }
+
+    #[test]
+    fn bad_cameras_are_rejected() {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let pos = Vector3::new(0.0, 5.0, -20.0);
+        assert!(matches!(Camera::look_at(pos, pos, up, 40.0), Err(Error::Camera(_))));
+
+        let mut cam = Camera::look_at(pos, Vector3::new(0.0, 0.0, 0.0), up, 180.0).unwrap();
+        let mut film = Film::new(4, 4).unwrap();
+        let result = raytracer(&mut film, &cam, &mut lit_floor(), &RenderSettings::new());
+        assert!(matches!(result, Err(Error::Camera(_))));
+
+        cam.fov = 40.0;
+        for height in [0.0, -1.0, f64::NAN] {
+            assert!(Projection::from_name("orthographic", Some(height)).is_err());
+            cam.projection = Projection::Orthographic { height };
+            assert!(matches!(cam.validate(), Err(Error::Camera(_))));
+        }
 
-            //draw triangle
-            ray.normalize_deg();
+        cam.projection = Projection::Perspective;
+        cam.pos.x = f64::NAN;
+        let result = raytracer(&mut film, &cam, &mut lit_floor(), &RenderSettings::new());
+        assert!(matches!(result, Err(Error::Camera(_))));
+    }
 
-            let color = ray_trace(&pos, &ray, &world, 0);
-            image.put_pixel(
-                x as u32,
-                y as u32,
-                Rgb([
-                    (color.r * 255.0) as u8,
-                    (color.g * 255.0) as u8,
-                    (color.b * 255.0) as u8,
-                ]),
-            );
+    #[test]
+    fn threads_do_not_change_the_image() {
+        let cam = Camera::look_at(Vector3::new(0.0, 5.0, -20.0), Vector3::new(0.0, 0.0, 0.0),
+                                  Vector3::new(0.0, 1.0, 0.0), 40.0).unwrap();
+        for integrator in [Integrator::Legacy, Integrator::PathTracer] {
+            let mut films = Vec::<Film>::new();
+            for threads in [1, 4] {
+                let mut settings = RenderSettings::new();
+                settings.threads = threads;
+                settings.tile_size = 4;
+                settings.samples = 4;
+                settings.integrator = integrator;
+                settings.passes = vec![Pass::Depth, Pass::Direct];
+                let mut film = Film::new(16, 12).unwrap();
+                raytracer(&mut film, &cam, &mut lit_floor(), &settings).unwrap();
+                films.push(film);
+            }
 
-            println!("Pixel Processed: {x} {y}");
-            
-            ray.zero();
+            for y in 0..12 {
+                for x in 0..16 {
+                    let bits = |film: &Film| {
+                        let mut films = vec![film];
+                        films.extend(film.passes().iter().map(|(_, pass)| pass));
+                        return films.iter()
+                            .map(|f| f.pixel(x, y))
+                            .flat_map(|c| [c.r, c.g, c.b, c.a].map(f64::to_bits))
+                            .collect::<Vec<u64>>();
+                    };
+                    assert_eq!(bits(&films[0]), bits(&films[1]), "pixel {x}, {y}");
+                }
+            }
         }
+    }
 
-        if x % 10 == 0 {
-            image.save("output.png").unwrap();
+    #[test]
+    fn oversized_films_are_rejected() {
+        for (width, height) in [(1 << 15, 1 << 14), (u32::MAX, u32::MAX)] {
+            assert!(matches!(Film::new(width, height),
+                             Err(Error::ImageTooLarge { .. })));
         }
+        assert_eq!(film::pixel_count(1 << 14, 1 << 14).unwrap(), film::MAX_PIXELS);
+    }
+
+    #[test]
+    fn integrators_agree_on_area_lights() {
+        assert_integrators_agree(&mut lit_floor());
+    }
+
+    #[test]
+    fn stratified_samples_stay_in_their_cell() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for count in 1..40usize {
+            let cols = (count as f64).sqrt().ceil() as usize;
+            let rows = count.div_ceil(cols);
+            for i in 0..count {
+                let (fx, fy) = footprint_position(Sampling::Stratified, i, count, &mut rng);
+                assert!((0.0..1.0).contains(&fx) && (0.0..1.0).contains(&fy), "{count} {i}");
+
+                let row = i / cols;
+                let row_cols = if row == rows - 1 { count - row * cols } else { cols };
+                assert_eq!((fy * rows as f64) as usize, row, "{count} {i}");
+                assert_eq!((fx * row_cols as f64) as usize, i % cols, "{count} {i}");
+            }
+        }
+
+        for _ in 0..1000 {
+            let (fx, fy) = footprint_position(Sampling::Jittered, 0, 16, &mut rng);
+            assert!((0.0..1.0).contains(&fx) && (0.0..1.0).contains(&fy));
+        }
+    }
+
+    #[test]
+    fn filters_keep_flat_images_flat() {
+        let cam = Camera::look_at(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0),
+                                  Vector3::new(0.0, 1.0, 0.0), 40.0).unwrap();
+        let mut world = World::new();
+        world.set_environment(Some(Environment::new_constant(Color::new(0.25, 0.5, 0.75, 1.0))));
+        for filter in [Filter::new_box(), Filter::new_tent(), Filter::new_gaussian(),
+                       Filter::new_mitchell()] {
+            let mut settings = RenderSettings::new();
+            settings.threads = 1;
+            settings.samples = 9;
+            settings.filter = filter;
+            let mut film = Film::new(4, 4).unwrap();
+            raytracer(&mut film, &cam, &mut world, &settings).unwrap();
+
+            for y in 0..4 {
+                for x in 0..4 {
+                    let pixel = film.pixel(x, y);
+                    for (value, expected) in [(pixel.r, 0.25), (pixel.g, 0.5), (pixel.b, 0.75)] {
+                        assert!((value - expected).abs() < 1e-9, "{filter:?}: {value}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn integrators_agree_on_environments() {
+        let mut world = lit_floor();
+        world.set_environment(Some(Environment::new_constant(Color::new(1.0, 1.0, 1.0, 1.0))));
+        assert_integrators_agree(&mut world);
     }
 }
@@ -0,0 +1,417 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::shape::Shape;
+use super::util::{BoundingBox, Vector3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhSettings {
+    // nodes with more shapes than this are always split
+    // (unless every centroid is in the same spot)
+    pub max_leaf_size: usize,
+    // number of buckets the centroids are sorted into per axis
+    pub bins: usize,
+    // relative cost of visiting a node against testing a shape
+    pub traversal_cost: f64
+}
+
+impl BvhSettings {
+    pub fn new() -> Self {
+        return Self {
+            max_leaf_size: 4,
+            bins: 16,
+            traversal_cost: 1.0
+        };
+    }
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// quality of the last built tree
+#[derive(Clone, Debug, Default)]
+pub struct BvhReport {
+    pub shapes: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    pub avg_leaf_size: f64,
+    // expected cost of a random ray, in shape tests
+    pub sah_cost: f64,
+    pub build_time: Duration
+}
+
+impl fmt::Display for BvhReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f,
+                      "bvh: {} shapes, {} nodes, {} leaves, depth {}, \
+                       leaf size {:.2} avg / {} max, sah cost {:.2}, built in {:.3}s",
+                      self.shapes, self.nodes, self.leaves, self.max_depth,
+                      self.avg_leaf_size, self.max_leaf_size, self.sah_cost,
+                      self.build_time.as_secs_f64());
+    }
+}
+
+struct Bounds {
+    min: Vector3,
+    max: Vector3
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        return Self {
+            min: Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)
+        };
+    }
+
+    fn grow(&mut self, min: &Vector3, max: &Vector3) {
+        self.min = Vector3::new(self.min.x.min(min.x), self.min.y.min(min.y), self.min.z.min(min.z));
+        self.max = Vector3::new(self.max.x.max(max.x), self.max.y.max(max.y), self.max.z.max(max.z));
+    }
+
+    fn area(&self) -> f64 {
+        let d = self.max.sub(&self.min);
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+
+        return 2.0 * (d.x * d.y + d.y * d.z + d.z * d.x);
+    }
+}
+
+fn axis(v: &Vector3, axis: usize) -> f64 {
+    return match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z
+    };
+}
+
+struct Builder<'a> {
+    settings: &'a BvhSettings,
+    mins: Vec<Vector3>,
+    maxs: Vec<Vector3>,
+    centroids: Vec<Vector3>,
+    order: Vec<usize>,
+    nodes: Vec<BoundingBox>,
+    report: BvhReport
+}
+
+impl Builder<'_> {
+    // builds the node for order[start..end] and returns its index
+    fn build(&mut self, start: usize, end: usize, depth: usize) -> usize {
+        let mut bounds = Bounds::empty();
+        let mut centroid_bounds = Bounds::empty();
+        for &i in self.order[start..end].iter() {
+            bounds.grow(&self.mins[i], &self.maxs[i]);
+            centroid_bounds.grow(&self.centroids[i], &self.centroids[i]);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(BoundingBox {
+            m1: bounds.min,
+            m2: bounds.max,
+            left: -1,
+            right: -1,
+            start,
+            count: end - start
+        });
+        self.report.max_depth = self.report.max_depth.max(depth);
+
+        let count = end - start;
+        let split = if count > 1 {
+            self.find_split(start, end, &bounds, &centroid_bounds)
+        } else {
+            None
+        };
+
+        let mid = match split {
+            Some((axis, pos, cost)) => {
+                if count <= self.settings.max_leaf_size && cost >= count as f64 {
+                    None
+                } else {
+                    Some(self.partition(start, end, axis, pos))
+                }
+            },
+            None => None
+        };
+
+        match mid {
+            Some(mid) => {
+                let left = self.build(start, mid, depth + 1);
+                let right = self.build(mid, end, depth + 1);
+                let node = &mut self.nodes[index];
+                node.left = left as isize;
+                node.right = right as isize;
+                node.count = 0;
+            },
+            None => {
+                self.report.leaves += 1;
+                self.report.max_leaf_size = self.report.max_leaf_size.max(count);
+            }
+        }
+
+        return index;
+    }
+
+    // binned sah, returns the axis, the split position and the cost
+    // of the best split in shape tests
+    fn find_split(&self, start: usize, end: usize,
+                  bounds: &Bounds, centroid_bounds: &Bounds) -> Option<(usize, f64, f64)> {
+        let bins = self.settings.bins.max(2);
+        let area = bounds.area();
+        let mut best: Option<(usize, f64, f64)> = None;
+
+        for a in 0..3 {
+            let lo = axis(&centroid_bounds.min, a);
+            let hi = axis(&centroid_bounds.max, a);
+            let extent = hi - lo;
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let scale = bins as f64 / extent;
+            let mut bin_bounds: Vec<Bounds> = (0..bins).map(|_| Bounds::empty()).collect();
+            let mut bin_counts = vec![0usize; bins];
+            for &i in self.order[start..end].iter() {
+                let b = (((axis(&self.centroids[i], a) - lo) * scale) as usize).min(bins - 1);
+                bin_bounds[b].grow(&self.mins[i], &self.maxs[i]);
+                bin_counts[b] += 1;
+            }
+
+            //sweep from the right to get the cost of everything after each plane
+            let mut right_area = vec![0.0; bins];
+            let mut right_count = vec![0usize; bins];
+            let mut acc = Bounds::empty();
+            let mut acc_count = 0;
+            for b in (1..bins).rev() {
+                acc.grow(&bin_bounds[b].min, &bin_bounds[b].max);
+                acc_count += bin_counts[b];
+                right_area[b] = acc.area();
+                right_count[b] = acc_count;
+            }
+
+            let mut acc = Bounds::empty();
+            let mut acc_count = 0;
+            for b in 1..bins {
+                acc.grow(&bin_bounds[b - 1].min, &bin_bounds[b - 1].max);
+                acc_count += bin_counts[b - 1];
+                if acc_count == 0 || right_count[b] == 0 {
+                    continue;
+                }
+
+                let cost = self.settings.traversal_cost +
+                    (acc.area() * acc_count as f64 +
+                     right_area[b] * right_count[b] as f64) / area;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((a, lo + b as f64 / scale, cost));
+                }
+            }
+        }
+
+        return best;
+    }
+
+    // moves everything with a centroid below pos to the front and
+    // returns where the second half starts
+    fn partition(&mut self, start: usize, end: usize, a: usize, pos: f64) -> usize {
+        let mut mid = start;
+        for i in start..end {
+            if axis(&self.centroids[self.order[i]], a) < pos {
+                self.order.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        //floating point can put the plane on the wrong side of a bin edge
+        if mid == start || mid == end {
+            mid = (start + end) / 2;
+        }
+
+        return mid;
+    }
+}
+
+// builds a top down bvh with the surface area heuristic. the shapes
+// are reordered so every leaf covers a contiguous range of them, the
+// returned order holds the old index of every shape.
+pub(crate) fn build(shapes: &mut Vec<Box<dyn Shape>>,
+                    settings: &BvhSettings) -> (Vec<BoundingBox>, Vec<usize>, BvhReport) {
+    let timer = Instant::now();
+
+    if shapes.is_empty() {
+        let report = BvhReport { build_time: timer.elapsed(), ..BvhReport::default() };
+        return (Vec::<BoundingBox>::new(), Vec::<usize>::new(), report);
+    }
+
+    let (mins, maxs): (Vec<Vector3>, Vec<Vector3>) = shapes.iter().map(|s| s.bounds()).unzip();
+    let centroids = mins.iter().zip(maxs.iter())
+        .map(|(min, max)| Vector3::new(
+            0.5 * (min.x + max.x), 0.5 * (min.y + max.y), 0.5 * (min.z + max.z)))
+        .collect();
+
+    let mut builder = Builder {
+        settings,
+        mins,
+        maxs,
+        centroids,
+        order: (0..shapes.len()).collect(),
+        nodes: Vec::<BoundingBox>::with_capacity(2 * shapes.len()),
+        report: BvhReport::default()
+    };
+    builder.build(0, shapes.len(), 0);
+
+    let mut slots: Vec<Option<Box<dyn Shape>>> = shapes.drain(..).map(Some).collect();
+    for &i in builder.order.iter() {
+        shapes.push(slots[i].take().unwrap());
+    }
+
+    let nodes = builder.nodes;
+    let mut report = builder.report;
+
+    let root_area = Bounds { min: nodes[0].m1, max: nodes[0].m2 }.area();
+    let mut cost = 0.0;
+    for node in nodes.iter() {
+        let area = Bounds { min: node.m1, max: node.m2 }.area();
+        let weight = if root_area > 0.0 { area / root_area } else { 1.0 };
+        cost += weight * if node.left == -1 {
+            node.count as f64
+        } else {
+            settings.traversal_cost
+        };
+    }
+
+    report.shapes = shapes.len();
+    report.nodes = nodes.len();
+    report.avg_leaf_size = shapes.len() as f64 / report.leaves as f64;
+    report.sah_cost = cost;
+    report.build_time = timer.elapsed();
+
+    return (nodes, builder.order, report);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::fksray::shape_collision;
+    use crate::fksray::util::{Color, Vector2, World};
+
+    fn random_point(rng: &mut SmallRng, size: f64) -> Vector3 {
+        return Vector3::new(rng.gen_range(-size..size), rng.gen_range(-size..size),
+                            rng.gen_range(-size..size));
+    }
+
+    // spheres and triangles scattered through a cube, some of them overlapping
+    fn world(leaf_size: usize) -> World {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut world = World::new();
+        let color = Color::new(1.0, 1.0, 1.0, 1.0);
+        let uv = Vector2::new(0.0, 0.0);
+        for _ in 0..100 {
+            let center = random_point(&mut rng, 50.0);
+            world.create_sphere(center, rng.gen_range(0.5..5.0), color, 0.0, -1, -1);
+
+            let p1 = random_point(&mut rng, 50.0);
+            let p2 = p1.add(&random_point(&mut rng, 5.0));
+            let p3 = p1.add(&random_point(&mut rng, 5.0));
+            world.create_triangle(p1, p2, p3, uv, uv, uv, color, color, color,
+                                  [0.0; 3], -1, -1);
+        }
+        world.set_bvh_settings(BvhSettings { max_leaf_size: leaf_size, ..BvhSettings::new() });
+        world.compute().unwrap();
+        return world;
+    }
+
+    fn contains(node: &BoundingBox, min: &Vector3, max: &Vector3) -> bool {
+        return node.m1.x <= min.x && node.m1.y <= min.y && node.m1.z <= min.z &&
+            node.m2.x >= max.x && node.m2.y >= max.y && node.m2.z >= max.z;
+    }
+
+    #[test]
+    fn bounds_contain_children() {
+        for leaf_size in [1, 4] {
+            let world = world(leaf_size);
+            for node in world.bbox.iter() {
+                if node.left == -1 {
+                    for shape in world.shapes[node.start..node.start + node.count].iter() {
+                        let (min, max) = shape.bounds();
+                        assert!(contains(node, &min, &max));
+                    }
+                    continue;
+                }
+
+                for child in [node.left as usize, node.right as usize] {
+                    let child = &world.bbox[child];
+                    assert!(contains(node, &child.m1, &child.m2));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_shape_in_one_leaf() {
+        for leaf_size in [1, 4] {
+            let world = world(leaf_size);
+            let mut reached = vec![0; world.shapes.len()];
+            let mut stack = vec![0];
+            while let Some(i) = stack.pop() {
+                let node = &world.bbox[i];
+                if node.left == -1 {
+                    assert!(node.count > 0);
+                    for r in reached[node.start..node.start + node.count].iter_mut() {
+                        *r += 1;
+                    }
+                } else {
+                    stack.push(node.left as usize);
+                    stack.push(node.right as usize);
+                }
+            }
+            assert!(reached.iter().all(|r| *r == 1));
+
+            let mut ids = world.shape_ids.clone();
+            ids.sort();
+            assert_eq!(ids, (0..world.shapes.len()).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn hits_match_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        for leaf_size in [1, 4] {
+            let world = world(leaf_size);
+            let mut hits = 0;
+            for _ in 0..2000 {
+                let origin = random_point(&mut rng, 70.0);
+                let mut dir = random_point(&mut rng, 1.0);
+                dir.normalize_dis();
+
+                let mut closest = f64::INFINITY;
+                for shape in world.shapes.iter() {
+                    if let Some((t, _, _)) = shape.intersect(&origin, &dir) {
+                        if t >= 0.0 && t < closest {
+                            closest = t;
+                        }
+                    }
+                }
+
+                let data = shape_collision(&origin, &dir, &world, 0.0, f64::INFINITY);
+                if closest == f64::INFINITY {
+                    assert_eq!(data.0, -1.0);
+                } else {
+                    assert_eq!(data.0, closest);
+                    hits += 1;
+                }
+            }
+            //the rays have to hit something for the test to mean anything
+            assert!(hits > 100);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use super::error::{Error, Result};
+use super::util::Vector3;
+
+// how points on the image map to rays
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    // rays through a flat image plane, fov spans the image height
+    #[default]
+    Perspective,
+    // parallel rays along forward, height is how much of the world the
+    // image height covers
+    Orthographic { height: f64 },
+    // equidistant fisheye, the angle to forward grows linearly from the
+    // image center and fov is the angle across the largest circle that
+    // fits in the image. anything outside the circle stays black
+    Fisheye,
+    // full 360 by 180 degree panorama, longitude runs along x and
+    // latitude along y. fov and aspect are ignored
+    Equirectangular
+}
+
+impl Projection {
+    // perspective, fisheye, equirectangular or orthographic, which also
+    // needs the height of the view
+    pub fn from_name(name: &str, height: Option<f64>) -> Result<Self> {
+        let projection = match (name, height) {
+            ("perspective", _) => Projection::Perspective,
+            ("orthographic", Some(height)) => Projection::Orthographic { height },
+            ("orthographic", None) => return Err(Error::Camera(
+                "the orthographic projection needs a height".to_string())),
+            ("fisheye", _) => Projection::Fisheye,
+            ("equirectangular", _) => Projection::Equirectangular,
+            _ => return Err(Error::Camera(format!("unknown projection \"{name}\"")))
+        };
+        projection.validate()?;
+        return Ok(projection);
+    }
+
+    // the parts that do not depend on the rest of the camera
+    fn validate(&self) -> Result<()> {
+        if let Projection::Orthographic { height } = *self {
+            if !(height.is_finite() && height > 0.0) {
+                return Err(Error::Camera(format!(
+                    "the orthographic height must be positive, not {height}")));
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+// thin lens camera looking down forward, the image plane is spanned by
+// right and up one unit in front of pos. with no aperture it is a pinhole
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub pos: Vector3,
+    pub(crate) forward: Vector3,
+    pub(crate) right: Vector3,
+    pub(crate) up: Vector3,
+    // vertical field of view in degrees
+    pub fov: f64,
+    // radius of the lens, 0 keeps everything sharp
+    pub aperture: f64,
+    // distance along forward of the plane that is in focus
+    pub focus_distance: f64,
+    pub projection: Projection
+}
+
+impl Camera {
+    // camera at pos looking at target, up only needs to point roughly up.
+    // the target starts out in focus
+    pub fn look_at(pos: Vector3, target: Vector3, up: Vector3, fov: f64) -> Result<Self> {
+        let mut forward = target.sub(&pos);
+        let dis2 = forward.normalize_dis();
+        if !(dis2 > 1e-12 && dis2.is_finite()) {
+            return Err(Error::Camera(
+                "the camera target must be away from the camera position".to_string()));
+        }
+        let focus_distance = dis2.sqrt();
+
+        let mut right = up.cross(&forward);
+        //up is parallel to the view direction, any perpendicular axis will do
+        if right.distance2() < 1e-12 {
+            let helper = if forward.x.abs() < 0.9 {
+                Vector3::new(1.0, 0.0, 0.0)
+            } else {
+                Vector3::new(0.0, 1.0, 0.0)
+            };
+            right = helper.cross(&forward);
+        }
+        right.normalize_dis();
+
+        let up = forward.cross(&right);
+
+        return Ok(Self {
+            pos,
+            forward,
+            right,
+            up,
+            fov,
+            aperture: 0.0,
+            focus_distance,
+            projection: Projection::Perspective
+        });
+    }
+
+    // the old euler setup: rot.x turns left and right, rot.y up and down
+    // and rot.z rolls the image, all in degrees
+    pub fn from_euler(pos: Vector3, rot: Vector3, fov: f64) -> Self {
+        let mut forward = Vector3::new(rot.x, rot.y, 0.0);
+        forward.normalize_deg();
+
+        let yaw = rot.x.to_radians();
+        let right = Vector3::new(yaw.cos(), 0.0, -yaw.sin());
+        let up = forward.cross(&right);
+
+        let (sinz, cosz) = rot.z.to_radians().sin_cos();
+        return Self {
+            pos,
+            forward,
+            right: right.scale(cosz).add(&up.scale(sinz)),
+            up: up.scale(cosz).sub(&right.scale(sinz)),
+            fov,
+            aperture: 0.0,
+            focus_distance: 1.0,
+            projection: Projection::Perspective
+        };
+    }
+
+    // whether the camera makes sense for its projection, a perspective
+    // view can not reach 180 degrees and a fisheye stops at 360
+    pub fn validate(&self) -> Result<()> {
+        if !self.pos.is_finite() || !self.forward.is_finite() ||
+            !self.right.is_finite() || !self.up.is_finite() {
+            return Err(Error::Camera("the camera position and direction must be finite".to_string()));
+        }
+        self.projection.validate()?;
+
+        let fov = match self.projection {
+            Projection::Perspective if !(self.fov > 0.0 && self.fov < 180.0) =>
+                Some("a perspective field of view must be above 0 and below 180 degrees"),
+            Projection::Fisheye if !(self.fov > 0.0 && self.fov <= 360.0) =>
+                Some("a fisheye field of view must be above 0 and at most 360 degrees"),
+            _ => None
+        };
+        if let Some(err) = fov {
+            return Err(Error::Camera(format!("{err}, not {}", self.fov)));
+        }
+
+        if self.aperture.is_nan() || self.aperture < 0.0 {
+            return Err(Error::Camera("the camera aperture can not be negative".to_string()));
+        }
+        if self.focus_distance.is_nan() || self.focus_distance <= 0.0 {
+            return Err(Error::Camera("the camera focus distance must be positive".to_string()));
+        }
+
+        return Ok(());
+    }
+
+    pub fn set_lens(&mut self, aperture: f64, focus_distance: f64) {
+        self.aperture = aperture;
+        self.focus_distance = focus_distance;
+    }
+
+    pub fn forward(&self) -> Vector3 {
+        return self.forward;
+    }
+
+    pub fn right(&self) -> Vector3 {
+        return self.right;
+    }
+
+    pub fn up(&self) -> Vector3 {
+        return self.up;
+    }
+
+    // origin and direction of the ray for a point on the image, x and y go
+    // from -1 to 1 across the image (y up) and aspect is the image width
+    // over its height. lens is a uniform sample in [0, 1)^2 that picks the
+    // spot on the lens the ray starts from. points the projection does not
+    // cover give None
+    pub(crate) fn ray(&self, x: f64, y: f64, aspect: f64,
+                      lens: (f64, f64)) -> Option<(Vector3, Vector3)> {
+        let (origin, mut dir) = match self.projection {
+            Projection::Perspective => {
+                let tan = (0.5 * self.fov).to_radians().tan();
+                let dir = self.forward
+                    .add(&self.right.scale(x * aspect * tan))
+                    .add(&self.up.scale(y * tan));
+                (self.pos, dir)
+            },
+            Projection::Orthographic { height } => {
+                let origin = self.pos
+                    .add(&self.right.scale(0.5 * x * aspect * height))
+                    .add(&self.up.scale(0.5 * y * height));
+                (origin, self.forward)
+            },
+            Projection::Fisheye => {
+                //1 at the edge of the circle
+                let px = x * aspect / aspect.min(1.0);
+                let py = y / aspect.min(1.0);
+                let r2 = px * px + py * py;
+                if r2 > 1.0 {
+                    return None;
+                }
+
+                let (sin_theta, cos_theta) = (r2.sqrt() * 0.5 * self.fov).to_radians().sin_cos();
+                let (sin_phi, cos_phi) = py.atan2(px).sin_cos();
+                let dir = self.forward.scale(cos_theta)
+                    .add(&self.right.scale(sin_theta * cos_phi))
+                    .add(&self.up.scale(sin_theta * sin_phi));
+                return Some((self.pos, dir));
+            },
+            Projection::Equirectangular => {
+                let (sin_lon, cos_lon) = (x * PI).sin_cos();
+                let (sin_lat, cos_lat) = (y * FRAC_PI_2).sin_cos();
+                let dir = self.forward.scale(cos_lat * cos_lon)
+                    .add(&self.right.scale(cos_lat * sin_lon))
+                    .add(&self.up.scale(sin_lat));
+                return Some((self.pos, dir));
+            }
+        };
+
+        //the lens only makes sense for the projections with a flat image plane
+        if self.aperture <= 0.0 {
+            dir.normalize_dis();
+            return Some((origin, dir));
+        }
+
+        //every ray through the same pixel meets again on the focus plane,
+        //dir is one unit long along forward for both projections
+        let focus = origin.add(&dir.scale(self.focus_distance));
+        let (lx, ly) = concentric_disk(lens.0, lens.1);
+        let origin = origin
+            .add(&self.right.scale(lx * self.aperture))
+            .add(&self.up.scale(ly * self.aperture));
+
+        let mut dir = focus.sub(&origin);
+        dir.normalize_dis();
+        return Some((origin, dir));
+    }
+}
+
+// maps the unit square onto the unit disk keeping the samples evenly
+// spread (shirley and chiu)
+fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+
+    return (r * theta.cos(), r * theta.sin());
+}
@@ -0,0 +1,202 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use image::Rgb32FImage;
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+use super::error::{Error, Result};
+use super::util::{Color, Vector3};
+
+// what rays that leave the scene see. it lights the scene like a light
+// infinitely far away, shading points send shadow rays towards it.
+//
+// the colors are radiance and y is up. the map is an equirectangular
+// image, its center looks along +z and its top row straight up. it is
+// importance sampled by the brightness of its pixels
+pub enum Environment {
+    Constant { color: Color },
+    // zenith straight up, horizon at y = 0 and ground below, linear in
+    // between
+    Gradient { zenith: Color, horizon: Color, ground: Color },
+    Map(EnvironmentMap)
+}
+
+pub struct EnvironmentMap {
+    img: Rgb32FImage,
+    intensity: f64,
+    // radians around y
+    rotation: f64,
+    // cumulative row weights, then the cumulative column weights of every
+    // row one after another, all ending at 1
+    marginal: Vec<f64>,
+    conditional: Vec<f64>
+}
+
+// a direction picked by Environment::sample
+#[derive(Clone, Copy, Debug)]
+pub struct EnvironmentSample {
+    pub dir: Vector3,
+    // radiance / pdf
+    pub weight: Color
+}
+
+fn mix(a: &Color, b: &Color, t: f64) -> Color {
+    return Color::new(
+        a.r + (b.r - a.r) * t,
+        a.g + (b.g - a.g) * t,
+        a.b + (b.b - a.b) * t,
+        1.0);
+}
+
+fn luminance(c: &[f32; 3]) -> f64 {
+    return 0.2126 * c[0] as f64 + 0.7152 * c[1] as f64 + 0.0722 * c[2] as f64;
+}
+
+// index of the first entry of the cumulative list above u
+fn search(cdf: &[f64], u: f64) -> usize {
+    return cdf.partition_point(|c| *c <= u).min(cdf.len() - 1);
+}
+
+// turns the weights into a cumulative list ending at 1, false if they
+// are all 0
+fn accumulate(weights: &mut [f64]) -> bool {
+    let mut sum = 0.0;
+    for w in weights.iter_mut() {
+        sum += *w;
+        *w = sum;
+    }
+    if sum <= 0.0 {
+        return false;
+    }
+
+    for w in weights.iter_mut() {
+        *w /= sum;
+    }
+    return true;
+}
+
+impl Environment {
+    pub fn new_constant(color: Color) -> Self {
+        return Environment::Constant { color };
+    }
+
+    pub fn new_gradient(zenith: Color, horizon: Color, ground: Color) -> Self {
+        return Environment::Gradient { zenith, horizon, ground };
+    }
+
+    // loads a .hdr, .exr or any other image, rotation is in degrees
+    pub fn new_map<P: AsRef<Path>>(path: P, intensity: f64, rotation: f64) -> Result<Self> {
+        let path = path.as_ref();
+        let img = match image::open(path) {
+            Ok(img) => img.to_rgb32f(),
+            Err(source) => return Err(Error::Image { path: path.display().to_string(), source })
+        };
+        let (width, height) = (img.width() as usize, img.height() as usize);
+
+        //a pixel covers less of the sphere the closer it is to a pole
+        let mut marginal = vec![0.0; height];
+        let mut conditional = vec![0.0; width * height];
+        for y in 0..height {
+            let sin = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let row = &mut conditional[y * width..(y + 1) * width];
+            for (x, w) in row.iter_mut().enumerate() {
+                *w = luminance(&img.get_pixel(x as u32, y as u32).0) * sin;
+            }
+            marginal[y] = row.iter().sum();
+            if !accumulate(row) {
+                row.iter_mut().for_each(|w| *w = 1.0);
+                accumulate(row);
+            }
+        }
+        if !accumulate(&mut marginal) {
+            return Err(Error::Scene(format!("{} is completely black", path.display())));
+        }
+
+        return Ok(Environment::Map(EnvironmentMap {
+            img, intensity, rotation: rotation.to_radians(), marginal, conditional
+        }));
+    }
+
+    pub fn radiance(&self, dir: &Vector3) -> Color {
+        let mut dir = *dir;
+        dir.normalize_dis();
+        return match self {
+            Environment::Constant { color } => *color,
+            Environment::Gradient { zenith, horizon, ground } => if dir.y >= 0.0 {
+                mix(horizon, zenith, dir.y)
+            } else {
+                mix(horizon, ground, -dir.y)
+            },
+            Environment::Map(map) => {
+                let (x, y) = map.pixel(&dir);
+                map.radiance(x, y)
+            }
+        };
+    }
+
+    pub fn sample(&self, rng: &mut SmallRng) -> Option<EnvironmentSample> {
+        let map = match self {
+            Environment::Map(map) => map,
+            _ => {
+                //uniform over the sphere
+                let z = 1.0 - 2.0 * rng.gen::<f64>();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+                let dir = Vector3::new(r * cos_phi, z, r * sin_phi);
+
+                let l = self.radiance(&dir);
+                let w = 4.0 * PI;
+                return Some(EnvironmentSample { dir, weight: Color::new(l.r * w, l.g * w, l.b * w, 1.0) });
+            }
+        };
+
+        let (width, height) = (map.img.width() as usize, map.img.height() as usize);
+        let y = search(&map.marginal, rng.gen());
+        let row = &map.conditional[y * width..(y + 1) * width];
+        let x = search(row, rng.gen());
+
+        //somewhere inside the pixel
+        let u = (x as f64 + rng.gen::<f64>()) / width as f64;
+        let v = (y as f64 + rng.gen::<f64>()) / height as f64;
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        let (sin_phi, cos_phi) = ((u - 0.5) * 2.0 * PI + map.rotation).sin_cos();
+        let dir = Vector3::new(sin_theta * sin_phi, cos_theta, sin_theta * cos_phi);
+
+        let p_row = map.marginal[y] - if y == 0 { 0.0 } else { map.marginal[y - 1] };
+        let p_col = row[x] - if x == 0 { 0.0 } else { row[x - 1] };
+        //the pixel probability over the solid angle it covers
+        let pdf = p_row * p_col * (width * height) as f64 / (2.0 * PI * PI * sin_theta);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let l = map.radiance(x, y);
+        let w = 1.0 / pdf;
+        return Some(EnvironmentSample { dir, weight: Color::new(l.r * w, l.g * w, l.b * w, 1.0) });
+    }
+}
+
+impl EnvironmentMap {
+    // pixel seen along the unit vector dir
+    fn pixel(&self, dir: &Vector3) -> (usize, usize) {
+        let (width, height) = (self.img.width() as usize, self.img.height() as usize);
+        let phi = f64::atan2(dir.x, dir.z) - self.rotation;
+        let u = (0.5 + phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        return (((u * width as f64) as usize).min(width - 1),
+                ((v * height as f64) as usize).min(height - 1));
+    }
+
+    fn radiance(&self, x: usize, y: usize) -> Color {
+        let pix = self.img.get_pixel(x as u32, y as u32).0;
+        return Color::new(
+            pix[0] as f64 * self.intensity,
+            pix[1] as f64 * self.intensity,
+            pix[2] as f64 * self.intensity,
+            1.0);
+    }
+}
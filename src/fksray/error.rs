@@ -0,0 +1,115 @@
+use std::fmt;
+use std::io;
+
+use super::film::MAX_PIXELS;
+
+// everything the renderer can reject instead of panicking on
+#[derive(Debug)]
+pub enum Error {
+    // a color string that is not #RRGGBB or #RRGGBBAA
+    InvalidColor(String),
+    // an image that could not be opened, decoded or saved
+    Image { path: String, source: image::ImageError },
+    // a triangle refers to a texture id that does not exist
+    UnknownTexture(isize),
+    // a shape refers to a material id that does not exist
+    UnknownMaterial(isize),
+    // a material with a roughness outside 0..1 or a bad ior, by id
+    InvalidMaterial(usize),
+    // a light with no area or direction, a non finite coordinate, a bad
+    // angle or a negative power, by id
+    InvalidLight(usize),
+    // compute() was called on a world without shapes, lights or environment
+    EmptyWorld,
+    // a shape that failed Shape::is_valid, like a triangle with a NaN
+    // coordinate or a sphere with a negative radius, by the order it was added in
+    InvalidShape(usize),
+    Io(io::Error),
+    // a scene file that is not valid json or does not match the format
+    Json(serde_json::Error),
+    // a scene file that is valid json but describes an invalid scene
+    Scene(String),
+    // an output file that can not be written the way it was asked for
+    Output(String),
+    // a checkpoint file that is damaged or was made for a different render
+    Checkpoint(String),
+    // a camera that looks nowhere or has a field of view its projection
+    // can not show
+    Camera(String),
+    // a film or checkpoint with more than film::MAX_PIXELS pixels
+    ImageTooLarge { width: u32, height: u32 },
+    Obj(tobj::LoadError)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// something the loader could work around, it is up to the caller to tell
+// the user about it
+#[derive(Debug)]
+pub enum Warning {
+    // the .mtl files of an .obj that could not be read, its meshes are white
+    ObjMaterials { path: String, source: tobj::LoadError }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Warning::ObjMaterials { path, source } =>
+                write!(f, "could not load the materials of {path}: {source}, using white instead")
+        };
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Error::InvalidColor(s) => write!(f, "invalid color string \"{s}\""),
+            Error::Image { path, source } => write!(f, "{path}: {source}"),
+            Error::UnknownTexture(id) => write!(f, "unknown texture id {id}"),
+            Error::UnknownMaterial(id) => write!(f, "unknown material id {id}"),
+            Error::InvalidMaterial(id) => write!(f, "material {id} has a bad roughness or ior"),
+            Error::InvalidLight(id) => write!(f, "light {id} has no area or direction, a bad angle or a bad power"),
+            Error::EmptyWorld => write!(f, "the world has no shapes, lights or environment"),
+            Error::InvalidShape(i) => write!(f, "shape {i} has a non finite coordinate or a bad size"),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Json(err) => write!(f, "{err}"),
+            Error::Scene(s) => write!(f, "{s}"),
+            Error::Output(s) => write!(f, "{s}"),
+            Error::Checkpoint(s) => write!(f, "{s}"),
+            Error::Camera(s) => write!(f, "{s}"),
+            Error::ImageTooLarge { width, height } =>
+                write!(f, "the image size {width}x{height} is larger than {MAX_PIXELS} pixels"),
+            Error::Obj(err) => write!(f, "{err}")
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Image { source, .. } => Some(source),
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Obj(err) => Some(err),
+            _ => None
+        };
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        return Error::Io(err);
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        return Error::Json(err);
+    }
+}
+
+impl From<tobj::LoadError> for Error {
+    fn from(err: tobj::LoadError) -> Self {
+        return Error::Obj(err);
+    }
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use image::{Rgb, RgbImage};
+
+use super::error::{self, Error};
+use super::pass::Pass;
+use super::util::Color;
+
+// squeezes linear radiance into 0..1 for 8 bit output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMap {
+    // everything above 1 saturates
+    #[default]
+    Clamp,
+    // x / (1 + x) per channel
+    Reinhard,
+    // narkowicz's fit of the aces filmic curve
+    Aces
+}
+
+impl ToneMap {
+    pub fn apply(&self, x: f64) -> f64 {
+        let x = x.max(0.0);
+        return match self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+        }.min(1.0);
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone mapping \"{s}\""))
+        };
+    }
+}
+
+// how the film is turned into an 8 bit image
+#[derive(Clone, Copy, Debug)]
+pub struct ToneSettings {
+    pub tone_map: ToneMap,
+    // in stops, every stop doubles the brightness before tone mapping
+    pub exposure: f64,
+    // encode with the srgb transfer curve, off writes the values linearly
+    pub srgb: bool
+}
+
+impl ToneSettings {
+    pub fn new() -> Self {
+        return Self {
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+            srgb: true
+        };
+    }
+
+    // a linear channel as an 8 bit value
+    pub fn encode(&self, x: f64) -> u8 {
+        let mut x = self.tone_map.apply(x * self.exposure.exp2());
+        if self.srgb {
+            x = if x <= 0.0031308 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 };
+        }
+
+        return (x * 255.0 + 0.5) as u8;
+    }
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// the largest image the renderer accepts, the film, its passes and the
+// progressive sums already take several gigabytes at this size
+pub const MAX_PIXELS: usize = 1 << 28;
+
+// pixels of a width x height buffer, an error above MAX_PIXELS
+pub fn pixel_count(width: u32, height: u32) -> error::Result<usize> {
+    return (width as usize).checked_mul(height as usize)
+        .filter(|count| *count <= MAX_PIXELS)
+        .ok_or(Error::ImageTooLarge { width, height });
+}
+
+// linear high dynamic range frame buffer the renderer writes into, with
+// a film of the same size for every render pass
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    passes: Vec<(Pass, Film)>
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> error::Result<Self> {
+        return Ok(Self {
+            width,
+            height,
+            pixels: vec![Color::new_zero(); pixel_count(width, height)?],
+            passes: Vec::<(Pass, Film)>::new()
+        });
+    }
+
+    pub fn width(&self) -> u32 {
+        return self.width;
+    }
+
+    pub fn height(&self) -> u32 {
+        return self.height;
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        return self.pixels[y as usize * self.width as usize + x as usize];
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[y as usize * self.width as usize + x as usize] = color;
+    }
+
+    // the passes in the order of RenderSettings::passes
+    pub fn passes(&self) -> &[(Pass, Film)] {
+        return &self.passes;
+    }
+
+    pub fn pass(&self, pass: Pass) -> Option<&Film> {
+        return self.passes.iter().find(|(p, _)| *p == pass).map(|(_, film)| film);
+    }
+
+    //starts every pass over from black
+    pub(crate) fn set_passes(&mut self, passes: &[Pass]) -> error::Result<()> {
+        self.passes = passes.iter()
+            .map(|pass| Ok((*pass, Film::new(self.width, self.height)?)))
+            .collect::<error::Result<Vec<(Pass, Film)>>>()?;
+        return Ok(());
+    }
+
+    pub(crate) fn set_pass_pixel(&mut self, i: usize, x: u32, y: u32, color: Color) {
+        self.passes[i].1.set_pixel(x, y, color);
+    }
+
+    pub fn to_image(&self, tone: &ToneSettings) -> RgbImage {
+        let mut image = RgbImage::new(self.width, self.height);
+        for (pixel, color) in image.pixels_mut().zip(self.pixels.iter()) {
+            *pixel = Rgb([tone.encode(color.r), tone.encode(color.g), tone.encode(color.b)]);
+        }
+
+        return image;
+    }
+}
@@ -0,0 +1,169 @@
+use std::str::FromStr;
+
+// pixel reconstruction filters, the radius is in pixels.
+// each pixel spreads its samples over [-radius, radius] around its
+// center and weighs them with the filter
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, alpha: f64 },
+    // b = c = 1/3 is the pair recommended by mitchell and netravali
+    MitchellNetravali { radius: f64, b: f64, c: f64 }
+}
+
+impl Filter {
+    pub fn new_box() -> Self {
+        return Filter::Box { radius: 0.5 };
+    }
+
+    pub fn new_tent() -> Self {
+        return Filter::Tent { radius: 1.0 };
+    }
+
+    pub fn new_gaussian() -> Self {
+        return Filter::Gaussian { radius: 1.5, alpha: 2.0 };
+    }
+
+    pub fn new_mitchell() -> Self {
+        return Filter::MitchellNetravali { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 };
+    }
+
+    pub fn radius(&self) -> f64 {
+        return match self {
+            Filter::Box { radius } => *radius,
+            Filter::Tent { radius } => *radius,
+            Filter::Gaussian { radius, .. } => *radius,
+            Filter::MitchellNetravali { radius, .. } => *radius
+        };
+    }
+
+    // weight of a sample dx, dy pixels away from the pixel center
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        return self.weight_1d(dx) * self.weight_1d(dy);
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let d = d.abs();
+        return match *self {
+            Filter::Box { radius } => {
+                if d <= radius { 1.0 } else { 0.0 }
+            },
+            Filter::Tent { radius } => {
+                (radius - d).max(0.0)
+            },
+            Filter::Gaussian { radius, alpha } => {
+                //shifted down so the weight reaches 0 at the radius
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            },
+            Filter::MitchellNetravali { radius, b, c } => {
+                //the polynomial is defined over -2..2
+                let x = 2.0 * d / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x +
+                     (-18.0 + 12.0 * b + 6.0 * c) * x * x +
+                     (6.0 - 2.0 * b)) / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x +
+                     (6.0 * b + 30.0 * c) * x * x +
+                     (-12.0 * b - 48.0 * c) * x +
+                     (8.0 * b + 24.0 * c)) / 6.0
+                } else {
+                    0.0
+                }
+            }
+        };
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        return Self::new_box();
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    // box, tent, gaussian or mitchell with their default radius
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "box" => Ok(Self::new_box()),
+            "tent" => Ok(Self::new_tent()),
+            "gaussian" => Ok(Self::new_gaussian()),
+            "mitchell" => Ok(Self::new_mitchell()),
+            _ => Err(format!("unknown filter \"{s}\""))
+        };
+    }
+}
+
+// where the samples of a pixel go inside the filter footprint
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    // one random sample in each cell of a grid
+    #[default]
+    Stratified,
+    // uniformly random samples
+    Jittered
+}
+
+impl FromStr for Sampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "stratified" => Ok(Sampling::Stratified),
+            "jittered" => Ok(Sampling::Jittered),
+            _ => Err(format!("unknown sampling \"{s}\""))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> [Filter; 4] {
+        return [Filter::new_box(), Filter::new_tent(), Filter::new_gaussian(), Filter::new_mitchell()];
+    }
+
+    #[test]
+    fn weights_end_at_the_radius() {
+        for filter in filters() {
+            let r = filter.radius();
+            assert!(filter.weight(0.0, 0.0) > 0.0, "{filter:?}");
+            for d in [r + 1e-9, r + 0.5, 10.0 * r] {
+                assert_eq!(filter.weight(d, 0.0), 0.0, "{filter:?}");
+                assert_eq!(filter.weight(0.0, -d), 0.0, "{filter:?}");
+            }
+            for d in [0.1, 0.3, 0.5 * r, 0.9 * r] {
+                assert_eq!(filter.weight(d, 0.2), filter.weight(-d, -0.2), "{filter:?}");
+            }
+        }
+    }
+
+    // a sample falls on the pixels around it with weights that sum to 1,
+    // so evenly spread samples give every pixel the same total weight
+    #[test]
+    fn weights_sum_to_one() {
+        for filter in [Filter::new_tent(), Filter::new_mitchell()] {
+            for i in 0..20 {
+                let offset = i as f64 / 20.0;
+                let mut sum = 0.0;
+                for x in -3..=3 {
+                    for y in -3..=3 {
+                        sum += filter.weight(x as f64 + offset, y as f64 + 0.3 * offset);
+                    }
+                }
+                assert!((sum - 1.0).abs() < 1e-9, "{filter:?} at {offset}: {sum}");
+            }
+        }
+
+        //the box covers exactly one pixel, edges excluded
+        for i in 1..20 {
+            let offset = i as f64 / 20.0 - 0.5;
+            let sum: f64 = (-3..=3).map(|x| Filter::new_box().weight(x as f64 + offset, 0.0)).sum();
+            assert_eq!(sum, 1.0);
+        }
+    }
+}
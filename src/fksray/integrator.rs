@@ -0,0 +1,236 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+use super::material::cosine_sample;
+use super::shape::Interaction;
+use super::stats;
+use super::util::{Color, Vector3, World};
+use super::{background, direct_light, light_collision, pass_direction, sample_lights,
+            shadow_transmittance, shape_collision};
+
+// how the color seen along a camera ray is worked out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    // point lights plus the old hemisphere scan and mirror reflections,
+    // bounded by RenderSettings::max_depth
+    #[default]
+    Legacy,
+    // unbiased monte carlo path tracing through the material bsdfs.
+    // shapes without a material are lambertian with a mirror lobe picked
+    // with the reflect value as its probability. paths end by russian
+    // roulette, or at RenderSettings::max_depth at the latest
+    PathTracer
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "legacy" => Ok(Integrator::Legacy),
+            "path" => Ok(Integrator::PathTracer),
+            _ => Err(format!("unknown integrator \"{s}\""))
+        };
+    }
+}
+
+// bounces before russian roulette can end a path
+const ROULETTE_DEPTH: usize = 3;
+
+// adds the light the path picks up at a hit on a shape with a material
+// and returns where the path goes next, and whether it went there by a
+// perfect mirror or window
+fn material_bounce(world: &World, data: (f64, f64, f64, isize), hit: &Interaction,
+                   dir: &Vector3, radiance: &mut Color, throughput: &mut Color,
+                   rng: &mut SmallRng) -> Option<(Vector3, bool)> {
+    let shape = &world.shapes[data.3 as usize];
+    let material = &world.materials[shape.material() as usize];
+
+    //outward shading normal
+    let mut norm = hit.norm;
+    if norm.dot(&hit.face_norm) < 0.0 {
+        norm = norm.scale(-1.0);
+    }
+
+    let surface = world.obtain_color(data.3 as usize, data.1, data.2);
+    let wo = dir.scale(-1.0);
+    let direct = direct_light(world, &hit.pos, &norm, &wo, material, &surface, 1, rng);
+    radiance.r += throughput.r * direct.r;
+    radiance.g += throughput.g * direct.g;
+    radiance.b += throughput.b * direct.b;
+
+    let sample = material.sample(&surface, &norm, &wo, rng)?;
+    throughput.mul_self(sample.weight.r, sample.weight.g, sample.weight.b, 1.0);
+    return Some((sample.dir, sample.delta));
+}
+
+// the same for shapes without a material, using their color and reflect
+fn surface_bounce(world: &World, data: (f64, f64, f64, isize), hit: &Interaction,
+                  dir: &Vector3, radiance: &mut Color, throughput: &mut Color,
+                  rng: &mut SmallRng) -> Option<(Vector3, bool)> {
+    let shape = &world.shapes[data.3 as usize];
+
+    let mut face_norm = hit.face_norm;
+    if face_norm.dot(dir) > 0.0 {
+        face_norm = face_norm.scale(-1.0);
+    }
+    let mut norm = hit.norm;
+    if norm.dot(&face_norm) < 0.0 {
+        norm = norm.scale(-1.0);
+    }
+
+    //the mirror lobe is picked with its own weight so the throughput
+    //does not change either way
+    let reflection = shape.reflect(data.1, data.2);
+    if rng.gen::<f64>() < reflection {
+        let dot_val = 2.0 * norm.dot(dir);
+        return Some((Vector3::new(
+            dir.x - dot_val * norm.x,
+            dir.y - dot_val * norm.y,
+            dir.z - dot_val * norm.z), true));
+    }
+
+    let albedo = world.obtain_color(data.3 as usize, data.1, data.2);
+
+    //next event estimation, point lights can never be hit by chance and
+    //the other lights are not counted when a diffuse bounce runs into them
+    for point in world.point_lights.iter() {
+        let mut light_dir = point.pos.sub(&hit.pos);
+        let dis2 = light_dir.normalize_dis();
+        let cos = norm.dot(&light_dir);
+        if cos <= 0.0 || light_dir.dot(&face_norm) <= 0.0 {
+            continue;
+        }
+
+        let transmittance = shadow_transmittance(&hit.pos, &light_dir, world,
+                                                 0.0, dis2.sqrt() - 0.001);
+        if transmittance <= 0.0 {
+            continue;
+        }
+
+        let inst = transmittance * point.lumen * cos / (PI * dis2);
+        radiance.r += throughput.r * albedo.r * point.color.r * inst;
+        radiance.g += throughput.g * albedo.g * point.color.g * inst;
+        radiance.b += throughput.b * albedo.b * point.color.b * inst;
+    }
+
+    sample_lights(world, &hit.pos, 1, rng, |light_dir, light| {
+        let cos = norm.dot(light_dir);
+        if cos <= 0.0 || light_dir.dot(&face_norm) <= 0.0 {
+            return;
+        }
+
+        let inst = cos / PI;
+        radiance.r += throughput.r * albedo.r * light.r * inst;
+        radiance.g += throughput.g * albedo.g * light.g * inst;
+        radiance.b += throughput.b * albedo.b * light.b * inst;
+    });
+
+    //the cosine and 1 / pi of the brdf cancel with the pdf
+    throughput.mul_self(albedo.r, albedo.g, albedo.b, 1.0);
+    let next = cosine_sample(&norm, rng);
+    if next.dot(&face_norm) <= 0.0 {
+        return None;
+    }
+
+    return Some((next, false));
+}
+
+// radiance arriving at pos along -ray and the part of it picked up before
+// the second bounce, see pass::Pass::Direct. the alpha is 1 when the path
+// scatters off anything or runs into a light and 0 when it only passes
+// through or misses
+pub(crate) fn path_trace(pos: &Vector3, ray: &Vector3, world: &World,
+                         max_depth: usize, rng: &mut SmallRng) -> (Color, Color) {
+    let mut radiance = Color::new_zero();
+    let mut throughput = Color::new(1.0, 1.0, 1.0, 1.0);
+    let mut origin = *pos;
+    let mut dir = *ray;
+
+    let mut bounce = 0;
+    //bounces counted like the legacy reflect_times
+    let mut depth = 0;
+    let mut scattered = false;
+    let mut direct = None;
+    //whether the path got here by mirrors and windows only, so the lights
+    //and environment it runs into were not sampled directly
+    let mut specular = true;
+    loop {
+        let data = shape_collision(&origin, &dir, world, 0.0, f64::INFINITY);
+        let tmax = if data.0 == -1.0 { f64::INFINITY } else { data.0 };
+        if let Some(emitted) = light_collision(&origin, &dir, world, tmax) {
+            if specular {
+                radiance.r += throughput.r * emitted.r;
+                radiance.g += throughput.g * emitted.g;
+                radiance.b += throughput.b * emitted.b;
+            }
+            if !scattered {
+                radiance.a = 1.0;
+            }
+            break;
+        }
+        if data.0 == -1.0 {
+            if specular {
+                let sky = background(&dir, world);
+                radiance.r += throughput.r * sky.r;
+                radiance.g += throughput.g * sky.g;
+                radiance.b += throughput.b * sky.b;
+            }
+            break;
+        }
+
+        let shape = &world.shapes[data.3 as usize];
+        let hit = shape.interaction(data.1, data.2);
+
+        //the transparent part of the surface is passed with its own
+        //probability so the throughput does not change, and the alpha
+        //averages out to the opacity of what the camera sees
+        let opacity = world.obtain_opacity(data.3 as usize, data.1, data.2);
+        let next = if opacity < 1.0 && rng.gen::<f64>() >= opacity {
+            //a straight pass does not count as a bounce, a refracted one does
+            let next = pass_direction(&dir, world, data);
+            if next.sub(&dir).dot(&next.sub(&dir)) > 1e-12 {
+                depth += 1;
+            }
+            Some((next, specular))
+        } else {
+            depth += 1;
+            let next = if shape.material() != -1 {
+                material_bounce(world, data, &hit, &dir, &mut radiance, &mut throughput, rng)
+            } else {
+                surface_bounce(world, data, &hit, &dir, &mut radiance, &mut throughput, rng)
+            };
+
+            if !scattered {
+                radiance.a = 1.0;
+                scattered = true;
+                direct = Some(radiance);
+            }
+            next
+        };
+        (dir, specular) = match next {
+            Some(next) => next,
+            None => break
+        };
+        if depth > max_depth {
+            break;
+        }
+        origin = hit.pos;
+
+        bounce += 1;
+        if bounce >= ROULETTE_DEPTH {
+            let survive = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+            if rng.gen::<f64>() >= survive {
+                break;
+            }
+            let inv = 1.0 / survive;
+            throughput.mul_self(inv, inv, inv, 1.0);
+        }
+        stats::count(|c| c.reflection_rays += 1);
+    }
+
+    return (radiance, direct.unwrap_or(radiance));
+}
@@ -0,0 +1,310 @@
+use std::f64::consts::PI;
+
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+use super::material::basis;
+use super::util::{Color, Vector3};
+
+// lights that are sampled with shadow rays, the color tints all of them.
+//
+// quads, triangles and spheres have a size and give soft shadows by
+// sending several shadow rays to random points on them. their power is
+// the radiant flux in watts, spread evenly over the surface as lambertian
+// emission. quads and triangles only emit to the side of (b - a) x (c - a),
+// so they are counter clockwise seen from the lit side. they are solid and
+// block shadow rays like any shape. camera rays and mirror bounces see
+// their radiance, other bounces that run into one stop there since the
+// light was already sampled directly.
+//
+// the sun is infinitely far away, its irradiance is in watts per square
+// meter on a surface facing it. with an angular diameter it is a disc in
+// the sky that casts soft shadows, otherwise a single direction.
+//
+// a spot is a point with an intensity in watts per steradian, the same as
+// the lumen of a point light, inside a cone. between the inner and outer
+// angle it fades out along a smoothstep raised to the falloff
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    // parallelogram from corner spanned by e1 and e2
+    Quad { corner: Vector3, e1: Vector3, e2: Vector3, radiance: Color },
+    Triangle { p1: Vector3, e1: Vector3, e2: Vector3, radiance: Color },
+    Sphere { center: Vector3, radius: f64, radiance: Color },
+    // to_sun is a unit vector, cone is 1 - cos of the angular radius
+    Sun { to_sun: Vector3, cone: f64, irradiance: Color },
+    // dir is a unit vector, the angles are stored as their cosines
+    Spot { pos: Vector3, dir: Vector3, cos_inner: f64, cos_outer: f64,
+           falloff: f64, intensity: Color }
+}
+
+// a point on a light picked by Light::sample
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    // unit direction from the shaded point to the light
+    pub dir: Vector3,
+    pub dist: f64,
+    // radiance / pdf, what bsdf * |cos| is multiplied with
+    pub weight: Color
+}
+
+const EPSILON: f64 = 0.0001;
+
+// radiance of a lambertian emitter with the given area and power
+fn radiance(color: &Color, power: f64, area: f64) -> Color {
+    return scaled(color, power / (PI * area));
+}
+
+fn scaled(color: &Color, s: f64) -> Color {
+    return Color::new(color.r * s, color.g * s, color.b * s, 1.0);
+}
+
+fn unit(mut v: Vector3) -> Vector3 {
+    v.normalize_dis();
+    return v;
+}
+
+// direction around axis with a uniform pdf over the cone, cone is
+// 1 - cos of its half angle
+fn cone_sample(axis: &Vector3, cone: f64, rng: &mut SmallRng) -> (Vector3, f64) {
+    let cos = 1.0 - rng.gen::<f64>() * cone;
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+    let (t, b) = basis(axis);
+    return (t.scale(sin * cos_phi).add(&b.scale(sin * sin_phi)).add(&axis.scale(cos)), cos);
+}
+
+// hit of the ray with the parallelogram (or triangle) p + u e1 + v e2 as t
+fn flat_intersect(ori: &Vector3, dir: &Vector3, p: &Vector3, e1: &Vector3, e2: &Vector3,
+                  triangle: bool) -> Option<f64> {
+    let pvec = dir.cross(e2);
+    let det = pvec.dot(e1);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = ori.sub(p);
+    let u = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(e1);
+    let v = dir.dot(&qvec) * inv_det;
+    if !(0.0..=1.0).contains(&v) || (triangle && u + v > 1.0) {
+        return None;
+    }
+
+    let t = e2.dot(&qvec) * inv_det;
+    return if t > EPSILON { Some(t) } else { None };
+}
+
+impl Light {
+    pub fn new_quad(corner: Vector3, a: Vector3, b: Vector3, color: Color, power: f64) -> Self {
+        let e1 = a.sub(&corner);
+        let e2 = b.sub(&corner);
+        let area = e1.cross(&e2).distance2().sqrt();
+        return Light::Quad { corner, e1, e2, radiance: radiance(&color, power, area) };
+    }
+
+    pub fn new_triangle(p1: Vector3, p2: Vector3, p3: Vector3, color: Color, power: f64) -> Self {
+        let e1 = p2.sub(&p1);
+        let e2 = p3.sub(&p1);
+        let area = 0.5 * e1.cross(&e2).distance2().sqrt();
+        return Light::Triangle { p1, e1, e2, radiance: radiance(&color, power, area) };
+    }
+
+    pub fn new_sphere(center: Vector3, radius: f64, color: Color, power: f64) -> Self {
+        let area = 4.0 * PI * radius * radius;
+        return Light::Sphere { center, radius, radiance: radiance(&color, power, area) };
+    }
+
+    // dir is where the light goes, angle the angular diameter in degrees
+    pub fn new_sun(dir: Vector3, angle: f64, color: Color, irradiance: f64) -> Self {
+        let to_sun = unit(dir.scale(-1.0));
+        let half = (0.5 * angle).to_radians();
+        let cone = 1.0 - half.cos();
+        return Light::Sun { to_sun, cone, irradiance: scaled(&color, irradiance) };
+    }
+
+    // the angles are between the axis and the edge of the cone in degrees
+    pub fn new_spot(pos: Vector3, dir: Vector3, inner: f64, outer: f64, falloff: f64,
+                    color: Color, intensity: f64) -> Self {
+        return Light::Spot {
+            pos,
+            dir: unit(dir),
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+            falloff,
+            intensity: scaled(&color, intensity)
+        };
+    }
+
+    // whether the light is a single point or direction, one shadow ray
+    // says all there is to know about it
+    pub fn is_delta(&self) -> bool {
+        return match *self {
+            Light::Sun { cone, .. } => cone <= 0.0,
+            Light::Spot { .. } => true,
+            _ => false
+        };
+    }
+
+    // radiance of the sun disc, the irradiance spread over its solid angle
+    // seen head on
+    fn sun_radiance(cone: f64, irradiance: &Color) -> Color {
+        let sin2 = cone * (2.0 - cone);
+        return scaled(irradiance, 1.0 / (PI * sin2));
+    }
+
+    // checked by World::compute
+    pub fn is_valid(&self) -> bool {
+        let color_valid = |c: &Color| [c.r, c.g, c.b].iter().all(|c| c.is_finite() && *c >= 0.0);
+        let (radiance, size) = match self {
+            Light::Quad { corner, e1, e2, radiance } |
+            Light::Triangle { p1: corner, e1, e2, radiance } =>
+                (radiance, if corner.is_finite() { e1.cross(e2).distance2() } else { f64::NAN }),
+            Light::Sphere { center, radius, radiance } =>
+                (radiance, if center.is_finite() { *radius } else { f64::NAN }),
+            Light::Sun { to_sun, cone, irradiance } =>
+                return to_sun.is_finite() && (0.0..1.0).contains(cone) && color_valid(irradiance),
+            Light::Spot { pos, dir, cos_inner, cos_outer, falloff, intensity } =>
+                return pos.is_finite() && dir.is_finite() &&
+                    (-1.0..=1.0).contains(cos_outer) && cos_inner >= cos_outer &&
+                    falloff.is_finite() && *falloff > 0.0 && color_valid(intensity)
+        };
+
+        return size.is_finite() && size > 0.0 && color_valid(radiance);
+    }
+
+    // picks a point on the light seen from pos, none when pos can not see
+    // the part of the light that was picked
+    pub fn sample(&self, pos: &Vector3, rng: &mut SmallRng) -> Option<LightSample> {
+        let (corner, e1, e2, radiance, triangle) = match self {
+            Light::Quad { corner, e1, e2, radiance } => (corner, e1, e2, radiance, false),
+            Light::Triangle { p1, e1, e2, radiance } => (p1, e1, e2, radiance, true),
+            Light::Sphere { center, radius, radiance } =>
+                return Self::sample_sphere(center, *radius, radiance, pos, rng),
+            Light::Sun { to_sun, cone, irradiance } =>
+                return Some(Self::sample_sun(to_sun, *cone, irradiance, rng)),
+            Light::Spot { pos: spot, dir, cos_inner, cos_outer, falloff, intensity } => {
+                let mut to_spot = spot.sub(pos);
+                let dis2 = to_spot.normalize_dis();
+                let cos = -to_spot.dot(dir);
+                if cos <= *cos_outer {
+                    return None;
+                }
+
+                let t = ((cos - cos_outer) / (cos_inner - cos_outer)).min(1.0);
+                let fade = (t * t * (3.0 - 2.0 * t)).powf(*falloff);
+                return Some(LightSample {
+                    dir: to_spot,
+                    dist: dis2.sqrt(),
+                    weight: scaled(intensity, fade / dis2)
+                });
+            }
+        };
+
+        //uniform over the area
+        let (mut u, mut v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if triangle && u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        let point = corner.add(&e1.scale(u)).add(&e2.scale(v));
+
+        let mut dir = point.sub(pos);
+        let dis2 = dir.normalize_dis();
+        let mut norm = e1.cross(e2);
+        let mut area = norm.normalize_dis().sqrt();
+        if triangle {
+            area *= 0.5;
+        }
+
+        let cos = -dir.dot(&norm);
+        if cos <= 0.0 {
+            return None;
+        }
+
+        //the area pdf turned into solid angle is dis2 / (area * cos)
+        let w = area * cos / dis2;
+        return Some(LightSample {
+            dir,
+            dist: dis2.sqrt(),
+            weight: Color::new(radiance.r * w, radiance.g * w, radiance.b * w, 1.0)
+        });
+    }
+
+    // uniform over the cone of directions the sphere covers
+    fn sample_sphere(center: &Vector3, radius: f64, radiance: &Color,
+                     pos: &Vector3, rng: &mut SmallRng) -> Option<LightSample> {
+        let mut axis = center.sub(pos);
+        let dis2 = axis.normalize_dis();
+        if dis2 <= radius * radius {
+            return None;
+        }
+
+        let sin2_max = radius * radius / dis2;
+        let cos_max = (1.0 - sin2_max).sqrt();
+        //1 - cos_max without the cancellation for small lights
+        let cone = sin2_max / (1.0 + cos_max);
+        let (dir, cos) = cone_sample(&axis, cone, rng);
+
+        //near side of the sphere along dir
+        let dis = dis2.sqrt();
+        let sin2 = 1.0 - cos * cos;
+        let dist = dis * cos - (radius * radius - dis2 * sin2).max(0.0).sqrt();
+
+        return Some(LightSample { dir, dist, weight: scaled(radiance, 2.0 * PI * cone) });
+    }
+
+    fn sample_sun(to_sun: &Vector3, cone: f64, irradiance: &Color,
+                  rng: &mut SmallRng) -> LightSample {
+        if cone <= 0.0 {
+            return LightSample { dir: *to_sun, dist: f64::INFINITY, weight: *irradiance };
+        }
+
+        let (dir, _) = cone_sample(to_sun, cone, rng);
+        let radiance = Self::sun_radiance(cone, irradiance);
+        return LightSample {
+            dir,
+            dist: f64::INFINITY,
+            weight: scaled(&radiance, 2.0 * PI * cone)
+        };
+    }
+
+    // closest hit in front of ori as (t, radiance), the radiance is zero
+    // on the back of quads and triangles. the sun disc is hit at infinity
+    // and spots can not be hit. dir does not have to be normalized
+    pub fn intersect(&self, ori: &Vector3, dir: &Vector3) -> Option<(f64, Color)> {
+        return match self {
+            Light::Quad { corner, e1, e2, radiance } |
+            Light::Triangle { p1: corner, e1, e2, radiance } => {
+                let triangle = matches!(self, Light::Triangle { .. });
+                let front = dir.dot(&e1.cross(e2)) < 0.0;
+                flat_intersect(ori, dir, corner, e1, e2, triangle)
+                    .map(|t| (t, if front { *radiance } else { Color::new(0.0, 0.0, 0.0, 1.0) }))
+            },
+            Light::Sphere { center, radius, radiance } => {
+                let oc = ori.sub(center);
+                let a = dir.dot(dir);
+                let b = oc.dot(dir);
+                let c = oc.dot(&oc) - radius * radius;
+                let disc = b * b - a * c;
+                if disc < 0.0 || c <= 0.0 {
+                    return None;
+                }
+
+                let t = (-b - disc.sqrt()) / a;
+                if t > EPSILON { Some((t, *radiance)) } else { None }
+            },
+            Light::Sun { to_sun, cone, irradiance } => {
+                if *cone <= 0.0 || unit(*dir).dot(to_sun) < 1.0 - cone {
+                    return None;
+                }
+                Some((f64::INFINITY, Self::sun_radiance(*cone, irradiance)))
+            },
+            Light::Spot { .. } => None
+        };
+    }
+}
@@ -0,0 +1,580 @@
+use std::f64::consts::PI;
+
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+use super::util::{Color, Vector3};
+
+// how a surface scatters light. the color of every material is multiplied
+// with the vertex color and texture of the shape it is on.
+//
+// roughness goes from 0 (a perfect mirror or window) to 1 and is squared
+// into the ggx alpha. ior is the index of refraction inside the surface,
+// the outside is assumed to be air
+#[derive(Clone, Copy, Debug)]
+pub enum Material {
+    // lambertian
+    Diffuse { color: Color },
+    // conductor with schlick fresnel, color is the reflectance head on
+    Metal { color: Color, roughness: f64 },
+    // dielectric that reflects and refracts, color tints what goes through
+    Glass { color: Color, ior: f64, roughness: f64 },
+    // diffuse base under a clear dielectric coat
+    Plastic { color: Color, ior: f64, roughness: f64 }
+}
+
+// a direction picked by Material::sample
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    pub dir: Vector3,
+    // bsdf * |cos| / pdf, what the light coming from dir is multiplied with
+    pub weight: Color,
+    pub pdf: f64,
+    // picked from a perfectly smooth lobe, eval and pdf are 0 for it
+    pub delta: bool
+}
+
+// roughness below this is treated as perfectly smooth
+const SMOOTH: f64 = 0.001;
+
+// any two unit vectors that make an orthonormal basis with n
+// (duff et al., building an orthonormal basis revisited)
+pub(crate) fn basis(n: &Vector3) -> (Vector3, Vector3) {
+    let sign = 1.0f64.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    return (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y));
+}
+
+// direction around n with a pdf of cos(theta) / pi
+pub(crate) fn cosine_sample(n: &Vector3, rng: &mut SmallRng) -> Vector3 {
+    let r = rng.gen::<f64>().sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+
+    let (t, b) = basis(n);
+    return t.scale(r * cos_phi).add(&b.scale(r * sin_phi)).add(&n.scale(z));
+}
+
+// w mirrored around n, both point away from the surface
+fn reflect(w: &Vector3, n: &Vector3) -> Vector3 {
+    return n.scale(2.0 * w.dot(n)).sub(w);
+}
+
+// w bent through a boundary with normal n on its side, eta is the ior on
+// the side of w over the ior on the other side. none on total internal
+// reflection
+fn refract(w: &Vector3, n: &Vector3, eta: f64) -> Option<Vector3> {
+    let cos_i = w.dot(n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    return Some(n.scale(eta * cos_i - cos_t).sub(&w.scale(eta)));
+}
+
+// fraction of unpolarized light reflected at a smooth boundary, cos_i is
+// on the side with ior eta_i
+fn fresnel_dielectric(cos_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_t = eta_i / eta_t * (1.0 - cos_i * cos_i).sqrt();
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin_t * sin_t).sqrt();
+    let parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    return 0.5 * (parallel * parallel + perpendicular * perpendicular);
+}
+
+fn fresnel_schlick(f0: &Color, cos: f64) -> Color {
+    let m = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    return Color::new(
+        f0.r + (1.0 - f0.r) * m,
+        f0.g + (1.0 - f0.g) * m,
+        f0.b + (1.0 - f0.b) * m,
+        1.0);
+}
+
+// trowbridge reitz distribution of microfacet normals h around n
+fn ggx_d(n: &Vector3, h: &Vector3, alpha: f64) -> f64 {
+    let cos = n.dot(h);
+    if cos <= 0.0 {
+        return 0.0;
+    }
+
+    let a2 = alpha * alpha;
+    let d = cos * cos * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn ggx_lambda(n: &Vector3, w: &Vector3, alpha: f64) -> f64 {
+    let cos2 = n.dot(w).powi(2);
+    if cos2 <= 0.0 {
+        return f64::INFINITY;
+    }
+
+    let tan2 = (1.0 - cos2) / cos2;
+    return 0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0);
+}
+
+// smith masking and shadowing
+fn ggx_g(n: &Vector3, wo: &Vector3, wi: &Vector3, alpha: f64) -> f64 {
+    return 1.0 / (1.0 + ggx_lambda(n, wo, alpha) + ggx_lambda(n, wi, alpha));
+}
+
+// microfacet normal with a pdf of d(h) * cos(theta_h)
+fn ggx_sample(n: &Vector3, alpha: f64, rng: &mut SmallRng) -> Vector3 {
+    let u = rng.gen::<f64>();
+    let cos2 = (1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u);
+    let sin = (1.0 - cos2).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+
+    let (t, b) = basis(n);
+    return t.scale(sin * cos_phi).add(&b.scale(sin * sin_phi)).add(&n.scale(cos2.sqrt()));
+}
+
+fn alpha(roughness: f64) -> f64 {
+    return (roughness * roughness).max(1e-6);
+}
+
+fn mul(a: &Color, b: &Color) -> Color {
+    return Color::new(a.r * b.r, a.g * b.g, a.b * b.b, a.a * b.a);
+}
+
+impl Material {
+    pub fn new_diffuse(color: Color) -> Self {
+        return Material::Diffuse { color };
+    }
+
+    pub fn new_metal(color: Color, roughness: f64) -> Self {
+        return Material::Metal { color, roughness };
+    }
+
+    pub fn new_glass(ior: f64, roughness: f64) -> Self {
+        return Material::Glass { color: Color::new(1.0, 1.0, 1.0, 1.0), ior, roughness };
+    }
+
+    pub fn new_plastic(color: Color, ior: f64, roughness: f64) -> Self {
+        return Material::Plastic { color, ior, roughness };
+    }
+
+    // checked by World::compute
+    pub fn is_valid(&self) -> bool {
+        return match *self {
+            Material::Diffuse { .. } => true,
+            Material::Metal { roughness, .. } => (0.0..=1.0).contains(&roughness),
+            Material::Glass { ior, roughness, .. } | Material::Plastic { ior, roughness, .. } =>
+                ior.is_finite() && ior > 0.0 && (0.0..=1.0).contains(&roughness)
+        };
+    }
+
+    pub(crate) fn color(&self) -> Color {
+        return match *self {
+            Material::Diffuse { color } |
+            Material::Metal { color, .. } |
+            Material::Glass { color, .. } |
+            Material::Plastic { color, .. } => color
+        };
+    }
+
+    // index of refraction of the surface, if it has one
+    pub fn ior(&self) -> Option<f64> {
+        return match *self {
+            Material::Glass { ior, .. } | Material::Plastic { ior, .. } => Some(ior),
+            _ => None
+        };
+    }
+
+    // where a ray along dir goes on after passing through the transparent
+    // part of a surface with outward normal n. it bends by the ior and
+    // mirrors on total internal reflection
+    pub fn pass_direction(&self, n: &Vector3, dir: &Vector3) -> Vector3 {
+        let ior = match self.ior() {
+            Some(ior) => ior,
+            None => return *dir
+        };
+
+        let wo = dir.scale(-1.0);
+        let (nf, eta) = if wo.dot(n) > 0.0 { (*n, 1.0 / ior) } else { (n.scale(-1.0), ior) };
+        return match refract(&wo, &nf, eta) {
+            Some(refracted) => refracted,
+            None => reflect(&wo, &nf)
+        };
+    }
+
+    // chance of sampling the coat of plastic instead of the base
+    fn coat_probability(ior: f64, cos_o: f64) -> f64 {
+        return fresnel_dielectric(cos_o, 1.0, ior).clamp(0.1, 0.9);
+    }
+
+    // bsdf * |cos(wi)| for light arriving from wi and leaving along wo.
+    // surface is the color of the shape at the hit and n its outward
+    // shading normal, wo and wi point away from the surface
+    pub fn eval(&self, surface: &Color, n: &Vector3, wo: &Vector3, wi: &Vector3) -> Color {
+        let cos_o = n.dot(wo);
+        let cos_i = n.dot(wi);
+        let tint = mul(surface, &self.color());
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return Color::new_zero();
+        }
+
+        //everything but glass only reflects
+        let same_side = cos_o * cos_i > 0.0;
+        let nf = if cos_o > 0.0 { *n } else { n.scale(-1.0) };
+        let mut f = Color::new_zero();
+
+        match *self {
+            Material::Diffuse { .. } => {
+                if same_side {
+                    f = tint;
+                    f.mul_self(cos_i.abs() / PI, cos_i.abs() / PI, cos_i.abs() / PI, 1.0);
+                }
+            },
+            Material::Metal { roughness, .. } => {
+                if same_side && roughness >= SMOOTH {
+                    let mut h = wo.add(wi);
+                    h.normalize_dis();
+                    let a = alpha(roughness);
+                    let s = ggx_d(&nf, &h, a) * ggx_g(&nf, wo, wi, a) / (4.0 * cos_o.abs());
+                    f = fresnel_schlick(&tint, wo.dot(&h));
+                    f.mul_self(s, s, s, 1.0);
+                }
+            },
+            Material::Plastic { ior, roughness, .. } => {
+                if same_side {
+                    let d = (1.0 - fresnel_dielectric(cos_o.abs(), 1.0, ior)) * cos_i.abs() / PI;
+                    let mut s = 0.0;
+                    if roughness >= SMOOTH {
+                        let mut h = wo.add(wi);
+                        h.normalize_dis();
+                        let a = alpha(roughness);
+                        s = fresnel_dielectric(wo.dot(&h), 1.0, ior) *
+                            ggx_d(&nf, &h, a) * ggx_g(&nf, wo, wi, a) / (4.0 * cos_o.abs());
+                    }
+                    f = Color::new(tint.r * d + s, tint.g * d + s, tint.b * d + s, 1.0);
+                }
+            },
+            Material::Glass { ior, roughness, .. } => {
+                if roughness >= SMOOTH {
+                    let (eta_o, eta_t) = if cos_o > 0.0 { (1.0, ior) } else { (ior, 1.0) };
+                    let a = alpha(roughness);
+                    if same_side {
+                        let mut h = wo.add(wi);
+                        h.normalize_dis();
+                        let s = fresnel_dielectric(wo.dot(&h), eta_o, eta_t) *
+                            ggx_d(&nf, &h, a) * ggx_g(&nf, wo, wi, a) / (4.0 * cos_o.abs());
+                        f = Color::new(s, s, s, 1.0);
+                    } else if let Some(h) = Self::transmission_half(&nf, wo, wi, eta_t / eta_o) {
+                        //walter et al., the eta^2 cancels with the radiance scaling
+                        let sd = wo.dot(&h) + eta_t / eta_o * wi.dot(&h);
+                        let s = (1.0 - fresnel_dielectric(wo.dot(&h), eta_o, eta_t)) *
+                            ggx_d(&nf, &h, a) * ggx_g(&nf, wo, wi, a) *
+                            wi.dot(&h).abs() * wo.dot(&h).abs() / (cos_o.abs() * sd * sd);
+                        f = tint;
+                        f.mul_self(s, s, s, 1.0);
+                    }
+                }
+            }
+        }
+
+        return f;
+    }
+
+    // microfacet normal that refracts wo into wi, on the side of nf
+    fn transmission_half(nf: &Vector3, wo: &Vector3, wi: &Vector3, eta: f64) -> Option<Vector3> {
+        let mut h = wo.add(&wi.scale(eta));
+        if h.distance2() == 0.0 {
+            return None;
+        }
+        h.normalize_dis();
+        if h.dot(nf) < 0.0 {
+            h = h.scale(-1.0);
+        }
+
+        //wo and wi have to be on opposite sides of the microfacet too
+        if wo.dot(&h) <= 0.0 || wi.dot(&h) >= 0.0 {
+            return None;
+        }
+        return Some(h);
+    }
+
+    // solid angle density of sample picking wi, 0 for smooth lobes
+    pub fn pdf(&self, n: &Vector3, wo: &Vector3, wi: &Vector3) -> f64 {
+        let cos_o = n.dot(wo);
+        let cos_i = n.dot(wi);
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return 0.0;
+        }
+
+        let same_side = cos_o * cos_i > 0.0;
+        let nf = if cos_o > 0.0 { *n } else { n.scale(-1.0) };
+
+        //density of the ggx reflection lobe
+        let reflection = |roughness: f64| -> f64 {
+            if roughness < SMOOTH {
+                return 0.0;
+            }
+            let mut h = wo.add(wi);
+            h.normalize_dis();
+            return ggx_d(&nf, &h, alpha(roughness)) * nf.dot(&h) / (4.0 * wo.dot(&h).abs());
+        };
+
+        return match *self {
+            Material::Diffuse { .. } => {
+                if same_side { cos_i.abs() / PI } else { 0.0 }
+            },
+            Material::Metal { roughness, .. } => {
+                if same_side { reflection(roughness) } else { 0.0 }
+            },
+            Material::Plastic { ior, roughness, .. } => {
+                if same_side {
+                    let p = Self::coat_probability(ior, cos_o.abs());
+                    p * reflection(roughness) + (1.0 - p) * cos_i.abs() / PI
+                } else {
+                    0.0
+                }
+            },
+            Material::Glass { ior, roughness, .. } => {
+                if roughness < SMOOTH {
+                    return 0.0;
+                }
+
+                let (eta_o, eta_t) = if cos_o > 0.0 { (1.0, ior) } else { (ior, 1.0) };
+                if same_side {
+                    let mut h = wo.add(wi);
+                    h.normalize_dis();
+                    fresnel_dielectric(wo.dot(&h), eta_o, eta_t) * reflection(roughness)
+                } else {
+                    let eta = eta_t / eta_o;
+                    match Self::transmission_half(&nf, wo, wi, eta) {
+                        Some(h) => {
+                            let sd = wo.dot(&h) + eta * wi.dot(&h);
+                            (1.0 - fresnel_dielectric(wo.dot(&h), eta_o, eta_t)) *
+                                ggx_d(&nf, &h, alpha(roughness)) * nf.dot(&h) *
+                                eta * eta * wi.dot(&h).abs() / (sd * sd)
+                        },
+                        None => 0.0
+                    }
+                }
+            }
+        };
+    }
+
+    // picks a direction for the light arriving at wo, none when the path
+    // should end (the sampled direction went below the surface)
+    pub fn sample(&self, surface: &Color, n: &Vector3, wo: &Vector3,
+                  rng: &mut SmallRng) -> Option<BsdfSample> {
+        let cos_o = n.dot(wo);
+        if cos_o == 0.0 {
+            return None;
+        }
+        let nf = if cos_o > 0.0 { *n } else { n.scale(-1.0) };
+        let tint = mul(surface, &self.color());
+
+        let dir = match *self {
+            Material::Diffuse { .. } => cosine_sample(&nf, rng),
+            Material::Metal { roughness, .. } => {
+                if roughness < SMOOTH {
+                    return Some(BsdfSample {
+                        dir: reflect(wo, &nf),
+                        weight: fresnel_schlick(&tint, cos_o.abs()),
+                        pdf: 1.0,
+                        delta: true
+                    });
+                }
+                reflect(wo, &ggx_sample(&nf, alpha(roughness), rng))
+            },
+            Material::Plastic { ior, roughness, .. } => {
+                let p = Self::coat_probability(ior, cos_o.abs());
+                if rng.gen::<f64>() >= p {
+                    cosine_sample(&nf, rng)
+                } else if roughness < SMOOTH {
+                    let s = fresnel_dielectric(cos_o.abs(), 1.0, ior) / p;
+                    return Some(BsdfSample {
+                        dir: reflect(wo, &nf),
+                        weight: Color::new(s, s, s, 1.0),
+                        pdf: p,
+                        delta: true
+                    });
+                } else {
+                    reflect(wo, &ggx_sample(&nf, alpha(roughness), rng))
+                }
+            },
+            Material::Glass { ior, roughness, .. } => {
+                let (eta_o, eta_t) = if cos_o > 0.0 { (1.0, ior) } else { (ior, 1.0) };
+                let h = if roughness < SMOOTH { nf } else { ggx_sample(&nf, alpha(roughness), rng) };
+                let cos_h = wo.dot(&h);
+                if cos_h <= 0.0 {
+                    return None;
+                }
+
+                let fresnel = fresnel_dielectric(cos_h, eta_o, eta_t);
+                let reflected = rng.gen::<f64>() < fresnel;
+                let dir = if reflected {
+                    reflect(wo, &h)
+                } else {
+                    refract(wo, &h, eta_o / eta_t)?
+                };
+
+                //a rough microfacet can send the ray to the wrong side
+                if (dir.dot(&nf) > 0.0) != reflected {
+                    return None;
+                }
+
+                if roughness < SMOOTH {
+                    //radiance is squeezed into a smaller solid angle going in
+                    let s = if reflected { 1.0 } else { (eta_o / eta_t).powi(2) };
+                    let weight = if reflected { Color::new(1.0, 1.0, 1.0, 1.0) } else { tint };
+                    return Some(BsdfSample {
+                        dir,
+                        weight: Color::new(weight.r * s, weight.g * s, weight.b * s, 1.0),
+                        pdf: if reflected { fresnel } else { 1.0 - fresnel },
+                        delta: true
+                    });
+                }
+                dir
+            }
+        };
+
+        let pdf = self.pdf(n, wo, &dir);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let mut weight = self.eval(surface, n, wo, &dir);
+        weight.mul_self(1.0 / pdf, 1.0 / pdf, 1.0 / pdf, 1.0);
+        return Some(BsdfSample { dir, weight, pdf, delta: false });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn white() -> Color {
+        return Color::new(1.0, 1.0, 1.0, 1.0);
+    }
+
+    fn normal() -> Vector3 {
+        return Vector3::new(0.0, 0.0, 1.0);
+    }
+
+    // unit direction cos_theta away from the normal
+    fn outgoing(cos_theta: f64) -> Vector3 {
+        return Vector3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+    }
+
+    const COUNT: usize = 200000;
+
+    // mean sample weight, how much of the light coming from a white
+    // furnace the material sends along wo
+    fn sampled_albedo(material: &Material, wo: &Vector3, rng: &mut SmallRng) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..COUNT {
+            if let Some(sample) = material.sample(&white(), &normal(), wo, rng) {
+                sum += sample.weight.r;
+            }
+        }
+        return sum / COUNT as f64;
+    }
+
+    // the same from eval alone, with directions spread evenly over the
+    // hemisphere
+    fn uniform_albedo(material: &Material, wo: &Vector3, rng: &mut SmallRng) -> f64 {
+        let (t, b) = basis(&normal());
+        let mut sum = 0.0;
+        for _ in 0..COUNT {
+            let z = rng.gen::<f64>();
+            let r = (1.0 - z * z).sqrt();
+            let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+            let wi = t.scale(r * cos_phi).add(&b.scale(r * sin_phi)).add(&normal().scale(z));
+            sum += material.eval(&white(), &normal(), wo, &wi).r * 2.0 * PI;
+        }
+        return sum / COUNT as f64;
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64, what: &str) {
+        assert!((a - b).abs() <= tolerance * b.abs().max(1.0), "{what}: {a} != {b}");
+    }
+
+    #[test]
+    fn sample_weights_match_eval() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let surface = Color::new(0.9, 0.7, 0.5, 1.0);
+        let tint = Color::new(0.8, 0.6, 0.4, 1.0);
+        let materials = [
+            Material::new_diffuse(tint),
+            Material::new_metal(tint, 0.0),
+            Material::new_metal(tint, 0.4),
+            Material::new_plastic(tint, 1.5, 0.0),
+            Material::new_plastic(tint, 1.5, 0.4),
+            Material::new_glass(1.5, 0.0),
+            Material::new_glass(1.5, 0.4)
+        ];
+
+        for material in &materials {
+            //glass is also hit from inside
+            for cos_o in [0.9, 0.5, 0.1, -0.7] {
+                let wo = outgoing(cos_o);
+                for _ in 0..1000 {
+                    let sample = match material.sample(&surface, &normal(), &wo, &mut rng) {
+                        Some(sample) => sample,
+                        None => continue
+                    };
+                    let what = format!("{material:?} at {cos_o}");
+                    assert_close(sample.dir.dot(&sample.dir), 1.0, 1e-9, &what);
+
+                    let eval = material.eval(&surface, &normal(), &wo, &sample.dir);
+                    let pdf = material.pdf(&normal(), &wo, &sample.dir);
+                    if sample.delta {
+                        //smooth plastic still has its diffuse base in that direction
+                        if !matches!(material, Material::Plastic { .. }) {
+                            assert_eq!((eval.r, eval.g, eval.b, pdf), (0.0, 0.0, 0.0, 0.0), "{what}");
+                        }
+                        assert!(sample.weight.r >= 0.0 && sample.weight.r.is_finite(), "{what}");
+                        continue;
+                    }
+
+                    assert_close(sample.pdf, pdf, 1e-9, &what);
+                    assert_close(sample.weight.r, eval.r / pdf, 1e-9, &what);
+                    assert_close(sample.weight.g, eval.g / pdf, 1e-9, &what);
+                    assert_close(sample.weight.b, eval.b / pdf, 1e-9, &what);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn white_furnace() {
+        let mut rng = SmallRng::seed_from_u64(11);
+        let diffuse = Material::new_diffuse(white());
+        for cos_o in [0.9, 0.5, 0.2] {
+            let wo = outgoing(cos_o);
+            assert_close(sampled_albedo(&diffuse, &wo, &mut rng), 1.0, 1e-9, "diffuse");
+            assert_close(uniform_albedo(&diffuse, &wo, &mut rng), 1.0, 0.01, "diffuse");
+        }
+
+        //single scattering ggx loses the light that bounces between
+        //microfacets, more of it the rougher the metal is
+        for roughness in [0.5, 0.8] {
+            let metal = Material::new_metal(white(), roughness);
+            for cos_o in [0.9, 0.5, 0.2] {
+                let wo = outgoing(cos_o);
+                let sampled = sampled_albedo(&metal, &wo, &mut rng);
+                let what = format!("roughness {roughness} at {cos_o}");
+                assert!(sampled > 0.5 && sampled <= 1.0, "{what}: {sampled}");
+                assert_close(uniform_albedo(&metal, &wo, &mut rng), sampled, 0.03, &what);
+            }
+        }
+        let smooth = sampled_albedo(&Material::new_metal(white(), 0.1), &outgoing(0.9), &mut rng);
+        assert!(smooth > 0.99 && smooth <= 1.0, "{smooth}");
+    }
+}
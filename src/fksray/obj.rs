@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tobj::LoadError;
+
+use super::error::{Result, Warning};
+use super::util::{Color, Vector2, Vector3, World};
+
+impl World {
+    // imports a wavefront .obj mesh (and the .mtl files it references),
+    // every vertex is scaled and then moved by pos and every triangle
+    // gets the material mat (or -1).
+    //
+    // the mtl diffuse color (Kd, with d as alpha) becomes the vertex color
+    // and map_Kd becomes the triangle texture. a missing or broken .mtl
+    // leaves every mesh white and is returned as a warning. missing or zero length
+    // normals fall back to the face normal and missing uvs to 0, 0.
+    //
+    // returns the number of triangles added and what went wrong on the way
+    pub fn load_obj<P: AsRef<Path>>(&mut self, path: P, pos: Vector3, scale: f64,
+                                    mat: isize) -> Result<(usize, Vec<Warning>)> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let (models, materials) = tobj::load_obj(path, &tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+            ..Default::default()
+        })?;
+        let mut warnings = Vec::<Warning>::new();
+        let materials = materials.unwrap_or_else(|source| {
+            warnings.push(Warning::ObjMaterials { path: path.display().to_string(), source });
+            Vec::<tobj::Material>::new()
+        });
+
+        //textures are shared between materials using the same image
+        let mut textures = HashMap::<String, isize>::new();
+        let mut looks = Vec::<(Color, isize)>::with_capacity(materials.len());
+        for material in materials.iter() {
+            let color = match material.diffuse {
+                Some(kd) => Color::new(kd[0] as f64, kd[1] as f64, kd[2] as f64,
+                                       material.dissolve.unwrap_or(1.0) as f64),
+                None => Color::new(1.0, 1.0, 1.0, material.dissolve.unwrap_or(1.0) as f64)
+            };
+
+            let tex = match &material.diffuse_texture {
+                Some(file) => match textures.get(file) {
+                    Some(id) => *id,
+                    None => {
+                        let id = self.create_texture(dir.join(file))?;
+                        textures.insert(file.clone(), id);
+                        id
+                    }
+                },
+                None => -1
+            };
+
+            looks.push((color, tex));
+        }
+
+        let mut count = 0;
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let (color, tex) = match mesh.material_id.and_then(|id| looks.get(id)) {
+                Some(look) => *look,
+                None => (Color::new(1.0, 1.0, 1.0, 1.0), -1)
+            };
+
+            //the uv and normal indices are empty when they share the position indices
+            let texcoord_indices = if mesh.texcoord_indices.is_empty() {
+                &mesh.indices
+            } else {
+                &mesh.texcoord_indices
+            };
+            let normal_indices = if mesh.normal_indices.is_empty() {
+                &mesh.indices
+            } else {
+                &mesh.normal_indices
+            };
+
+            for face in 0..mesh.indices.len() / 3 {
+                let mut p = [Vector3::new_zero(); 3];
+                let mut uv = [Vector2::new(0.0, 0.0); 3];
+                let mut n = [Vector3::new_zero(); 3];
+
+                for k in 0..3 {
+                    let i = 3 * face + k;
+                    let vi = 3 * mesh.indices[i] as usize;
+                    let position = mesh.positions.get(vi..vi + 3)
+                        .ok_or(LoadError::FaceVertexOutOfBounds)?;
+                    p[k] = Vector3::new(
+                        pos.x + scale * position[0] as f64,
+                        pos.y + scale * position[1] as f64,
+                        pos.z + scale * position[2] as f64);
+
+                    if !mesh.texcoords.is_empty() {
+                        let ti = 2 * *texcoord_indices.get(i)
+                            .ok_or(LoadError::FaceTexCoordOutOfBounds)? as usize;
+                        let texcoord = mesh.texcoords.get(ti..ti + 2)
+                            .ok_or(LoadError::FaceTexCoordOutOfBounds)?;
+                        uv[k] = Vector2::new(texcoord[0] as f64, texcoord[1] as f64);
+                    }
+
+                    if !mesh.normals.is_empty() {
+                        let ni = 3 * *normal_indices.get(i)
+                            .ok_or(LoadError::FaceNormalOutOfBounds)? as usize;
+                        let normal = mesh.normals.get(ni..ni + 3)
+                            .ok_or(LoadError::FaceNormalOutOfBounds)?;
+                        n[k] = Vector3::new(normal[0] as f64, normal[1] as f64, normal[2] as f64);
+                        n[k].normalize_dis();
+                    }
+                }
+
+                //a zero length normal has no direction to interpolate
+                let mut face_norm = p[1].sub(&p[0]).cross(&p[2].sub(&p[0]));
+                face_norm.normalize_dis();
+                for normal in n.iter_mut() {
+                    if !normal.is_finite() {
+                        *normal = face_norm;
+                    }
+                }
+
+                if mesh.normals.is_empty() {
+                    self.create_triangle(p[0], p[1], p[2], uv[0], uv[1], uv[2],
+                                         color, color, color, [0.0; 3], tex, mat);
+                } else {
+                    self.create_smooth_triangle(p[0], p[1], p[2], n[0], n[1], n[2],
+                                                uv[0], uv[1], uv[2],
+                                                color, color, color, [0.0; 3], tex, mat);
+                }
+                count += 1;
+            }
+        }
+
+        return Ok((count, warnings));
+    }
+}
@@ -0,0 +1,290 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::error::{Error, Result};
+use super::film::{Film, ToneSettings};
+
+// one named layer of an exr file. the i-th channel name takes r, g, b and
+// a of the film in that order, so ["Z"] writes only r. the beauty layer
+// has an empty name and its channels are written without a prefix
+pub struct Layer<'a> {
+    pub name: &'a str,
+    pub film: &'a Film,
+    pub channels: &'a [&'a str]
+}
+
+impl<'a> Layer<'a> {
+    pub fn new(name: &'a str, film: &'a Film, channels: &'a [&'a str]) -> Self {
+        return Self { name, film, channels };
+    }
+
+    // r, g, b and a without a prefix
+    pub fn new_beauty(film: &'a Film) -> Self {
+        return Self::new("", film, &["R", "G", "B", "A"]);
+    }
+}
+
+// writes the film by the extension of the path: linear floats for .exr
+// and .pfm, tone mapped 8 bit for everything else. render passes become
+// layers of an exr, other formats get one <name>.<pass>.exr per pass
+pub fn save<P: AsRef<Path>>(path: P, film: &Film, tone: &ToneSettings) -> Result<()> {
+    let path = path.as_ref();
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    if extension.as_deref() == Some("exr") {
+        let mut layers = vec![Layer::new_beauty(film)];
+        for (pass, pass_film) in film.passes() {
+            layers.push(Layer::new(pass.name(), pass_film, pass.channels()));
+        }
+        return write_exr(path, &layers);
+    }
+
+    match extension.as_deref() {
+        Some("pfm") => write_pfm(path, film)?,
+        _ => film.to_image(tone).save(path)
+            .map_err(|source| Error::Image { path: path.display().to_string(), source })?
+    }
+
+    for (pass, pass_film) in film.passes() {
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.exr", pass.name()));
+        let layer = Layer::new("", pass_film, pass.channels());
+        write_exr(path.with_file_name(name), &[layer])?;
+    }
+
+    return Ok(());
+}
+
+fn component(film: &Film, x: u32, y: u32, i: usize) -> f32 {
+    let color = film.pixel(x, y);
+    return [color.r, color.g, color.b, color.a][i] as f32;
+}
+
+fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+// uncompressed scanline openexr with 32 bit float channels. every layer
+// has to be the size of the first one
+pub fn write_exr<P: AsRef<Path>>(path: P, layers: &[Layer]) -> Result<()> {
+    let (width, height) = match layers.first() {
+        Some(layer) => (layer.film.width(), layer.film.height()),
+        None => return Err(Error::Output("an exr file needs at least one layer".to_string()))
+    };
+    if layers.iter().any(|l| l.film.width() != width || l.film.height() != height) {
+        return Err(Error::Output("every exr layer needs the same size".to_string()));
+    }
+
+    //the format wants the channels sorted by name, as (name, layer, component)
+    let mut channels = Vec::<(String, usize, usize)>::new();
+    for (l, layer) in layers.iter().enumerate() {
+        for (i, channel) in layer.channels.iter().take(4).enumerate() {
+            let name = if layer.name.is_empty() {
+                channel.to_string()
+            } else {
+                format!("{}.{}", layer.name, channel)
+            };
+            channels.push((name, l, i));
+        }
+    }
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+    if channels.windows(2).any(|w| w[0].0 == w[1].0) {
+        return Err(Error::Output("exr channel names must be unique".to_string()));
+    }
+
+    let mut header = Vec::<u8>::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut chlist = Vec::<u8>::new();
+    for (name, _, _) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        //float, linear, reserved, no subsampling
+        chlist.extend_from_slice(&2i32.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut window = Vec::<u8>::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    //one scanline per block, the offset table points at each of them
+    let line_size = channels.len() * width as usize * 4;
+    let first_line = header.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        let offset = (first_line + y * (8 + line_size)) as u64;
+        header.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+
+    let mut line = Vec::<u8>::with_capacity(8 + line_size);
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for (_, l, i) in channels.iter() {
+            for x in 0..width {
+                line.extend_from_slice(&component(layers[*l].film, x, y, *i).to_le_bytes());
+            }
+        }
+        out.write_all(&line)?;
+    }
+
+    out.flush()?;
+    return Ok(());
+}
+
+// portable float map with the rgb of the film, the rows go bottom to top
+pub fn write_pfm<P: AsRef<Path>>(path: P, film: &Film) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    //a negative scale means little endian
+    write!(out, "PF\n{} {}\n-1.0\n", film.width(), film.height())?;
+
+    for y in (0..film.height()).rev() {
+        for x in 0..film.width() {
+            for i in 0..3 {
+                out.write_all(&component(film, x, y, i).to_le_bytes())?;
+            }
+        }
+    }
+
+    out.flush()?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fksray::pass::Pass;
+    use crate::fksray::util::{temp_path, Color};
+
+    fn read_string(bytes: &[u8], pos: &mut usize) -> String {
+        let end = *pos + bytes[*pos..].iter().position(|b| *b == 0).unwrap();
+        let s = String::from_utf8(bytes[*pos..end].to_vec()).unwrap();
+        *pos = end + 1;
+        return s;
+    }
+
+    fn read_i32(bytes: &[u8], pos: usize) -> i32 {
+        return i32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+    }
+
+    // the attributes of an exr header by name, and where the header ends
+    fn read_header(bytes: &[u8]) -> (Vec<(String, String, Vec<u8>)>, usize) {
+        assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let mut attributes = Vec::<(String, String, Vec<u8>)>::new();
+        let mut pos = 8;
+        while bytes[pos] != 0 {
+            let name = read_string(bytes, &mut pos);
+            let kind = read_string(bytes, &mut pos);
+            let size = read_i32(bytes, pos) as usize;
+            attributes.push((name, kind, bytes[pos + 4..pos + 4 + size].to_vec()));
+            pos += 4 + size;
+        }
+        return (attributes, pos + 1);
+    }
+
+    // a 3x2 film whose pixel x, y is (x, y, 0.5, 1) with a depth pass of x + y
+    fn film() -> Film {
+        let mut film = Film::new(3, 2).unwrap();
+        film.set_passes(&[Pass::Depth]).unwrap();
+        for y in 0..2 {
+            for x in 0..3 {
+                film.set_pixel(x, y, Color::new(x as f64, y as f64, 0.5, 1.0));
+                film.set_pass_pixel(0, x, y, Color::new((x + y) as f64, 0.0, 0.0, 0.0));
+            }
+        }
+        return film;
+    }
+
+    #[test]
+    fn exr_header_and_channels_read_back() {
+        let path = temp_path("layers.exr");
+        save(&path, &film(), &ToneSettings::new()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let (attributes, end) = read_header(&bytes);
+        let attribute = |name: &str| attributes.iter()
+            .find(|a| a.0 == name).map(|a| (a.1.as_str(), a.2.as_slice())).unwrap();
+
+        let (kind, chlist) = attribute("channels");
+        assert_eq!(kind, "chlist");
+        let mut names = Vec::<String>::new();
+        let mut pos = 0;
+        while chlist[pos] != 0 {
+            names.push(read_string(chlist, &mut pos));
+            //every channel is a float
+            assert_eq!(read_i32(chlist, pos), 2);
+            pos += 16;
+        }
+        assert_eq!(names, ["A", "B", "G", "R", "depth.Z"]);
+
+        let (kind, window) = attribute("dataWindow");
+        assert_eq!(kind, "box2i");
+        let window = (0..4).map(|i| read_i32(window, 4 * i)).collect::<Vec<i32>>();
+        assert_eq!(window, [0, 0, 2, 1]);
+        assert_eq!(attribute("compression").1, &[0]);
+
+        //the second scanline, through the offset table
+        let offset = u64::from_le_bytes(bytes[end + 8..end + 16].try_into().unwrap()) as usize;
+        assert_eq!(read_i32(&bytes, offset), 1);
+        assert_eq!(read_i32(&bytes, offset + 4), 5 * 3 * 4);
+        let line = (0..15)
+            .map(|i| f32::from_le_bytes(bytes[offset + 8 + 4 * i..offset + 12 + 4 * i].try_into().unwrap()))
+            .collect::<Vec<f32>>();
+        assert_eq!(line, [1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 1.0, 1.0, 1.0,
+                          0.0, 1.0, 2.0, 1.0, 2.0, 3.0]);
+        assert_eq!(bytes.len(), offset + 8 + 15 * 4);
+    }
+
+    #[test]
+    fn passes_beside_other_formats() {
+        let path = temp_path("beside.pfm");
+        save(&path, &film(), &ToneSettings::new()).unwrap();
+        let pass_path = temp_path("beside.depth.exr");
+        let bytes = fs::read(&pass_path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&pass_path).unwrap();
+
+        let (attributes, _) = read_header(&bytes);
+        let chlist = &attributes.iter().find(|a| a.0 == "channels").unwrap().2;
+        assert_eq!(read_string(chlist, &mut 0), "Z");
+        assert_eq!(chlist[18], 0);
+    }
+
+    #[test]
+    fn exr_layers_must_match() {
+        let small = Film::new(2, 2).unwrap();
+        let big = Film::new(3, 2).unwrap();
+        let layers = [Layer::new_beauty(&small), Layer::new("big", &big, &["Y"])];
+        assert!(matches!(write_exr(temp_path("mismatch.exr"), &layers), Err(Error::Output(_))));
+        assert!(matches!(write_exr(temp_path("empty.exr"), &[]), Err(Error::Output(_))));
+    }
+}
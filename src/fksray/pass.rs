@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+use super::util::{Color, Vector2, Vector3};
+
+// extra images rendered next to the beauty pass, see RenderSettings::passes.
+// they describe what the camera ray hits first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    // distance from the camera, infinite where nothing is hit
+    Depth,
+    // world space shading normal, turned towards the camera
+    Normal,
+    // vertex color times texture times material color
+    Albedo,
+    Uv,
+    // index of the shape in the order it was added, -1 where nothing is hit
+    ShapeId,
+    // texture id, -1 without a texture
+    TextureId,
+    // light that reaches the camera after at most one bounce, including
+    // lights and the environment seen directly
+    Direct,
+    // the rest of the beauty pass, everything reflected or refracted
+    Reflection,
+    Alpha
+}
+
+// what the passes need to know about one camera ray
+pub(crate) struct PassSample {
+    pub(crate) depth: f64,
+    pub(crate) normal: Vector3,
+    pub(crate) albedo: Color,
+    pub(crate) uv: Vector2,
+    pub(crate) shape: isize,
+    pub(crate) texture: isize,
+    pub(crate) direct: Color
+}
+
+impl PassSample {
+    pub(crate) fn new_miss() -> Self {
+        return Self {
+            depth: f64::INFINITY,
+            normal: Vector3::new_zero(),
+            albedo: Color::new_zero(),
+            uv: Vector2::new(0.0, 0.0),
+            shape: -1,
+            texture: -1,
+            direct: Color::new_zero()
+        };
+    }
+}
+
+impl Pass {
+    pub const ALL: [Pass; 9] = [
+        Pass::Depth, Pass::Normal, Pass::Albedo, Pass::Uv, Pass::ShapeId,
+        Pass::TextureId, Pass::Direct, Pass::Reflection, Pass::Alpha
+    ];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Pass::Depth => "depth",
+            Pass::Normal => "normal",
+            Pass::Albedo => "albedo",
+            Pass::Uv => "uv",
+            Pass::ShapeId => "shape_id",
+            Pass::TextureId => "texture_id",
+            Pass::Direct => "direct",
+            Pass::Reflection => "reflection",
+            Pass::Alpha => "alpha"
+        };
+    }
+
+    // channel names in an exr file, they take r, g, b and a in order
+    pub fn channels(&self) -> &'static [&'static str] {
+        return match self {
+            Pass::Depth => &["Z"],
+            Pass::Normal => &["X", "Y", "Z"],
+            Pass::Albedo | Pass::Direct | Pass::Reflection => &["R", "G", "B"],
+            Pass::Uv => &["U", "V"],
+            Pass::ShapeId | Pass::TextureId => &["id"],
+            Pass::Alpha => &["A"]
+        };
+    }
+
+    // whether the samples of a pixel are averaged with the pixel filter.
+    // the others take the sample nearest to the pixel center since an
+    // average of ids or of a near and a far depth means nothing
+    pub fn is_filtered(&self) -> bool {
+        return !matches!(self, Pass::Depth | Pass::ShapeId | Pass::TextureId);
+    }
+
+    // the value of one sample with the given beauty color
+    pub(crate) fn value(&self, color: &Color, sample: &PassSample) -> Color {
+        return match self {
+            Pass::Depth => Color::new(sample.depth, 0.0, 0.0, 0.0),
+            Pass::Normal => Color::new(sample.normal.x, sample.normal.y, sample.normal.z, 0.0),
+            Pass::Albedo => sample.albedo,
+            Pass::Uv => Color::new(sample.uv.x, sample.uv.y, 0.0, 0.0),
+            Pass::ShapeId => Color::new(sample.shape as f64, 0.0, 0.0, 0.0),
+            Pass::TextureId => Color::new(sample.texture as f64, 0.0, 0.0, 0.0),
+            Pass::Direct => sample.direct,
+            Pass::Reflection => Color::new(color.r - sample.direct.r, color.g - sample.direct.g,
+                                           color.b - sample.direct.b, 0.0),
+            Pass::Alpha => Color::new(color.a, 0.0, 0.0, 0.0)
+        };
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Pass::ALL.iter().find(|pass| pass.name() == s).copied()
+            .ok_or_else(|| format!("unknown render pass \"{s}\""));
+    }
+}
@@ -0,0 +1,542 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::Crop;
+use super::error::{Error, Result};
+use super::film::{self, Film};
+use super::filter::{Filter, Sampling};
+use super::integrator::Integrator;
+use super::pass::{Pass, PassSample};
+use super::util::Color;
+
+const MAGIC: &[u8; 8] = b"FKSCKPT2";
+
+fn add_weighted(sum: &mut Color, color: &Color, weight: f64) {
+    sum.r += weight * color.r;
+    sum.g += weight * color.g;
+    sum.b += weight * color.b;
+    sum.a += weight * color.a;
+}
+
+// running sums of the samples of one pixel. filtered passes keep a
+// weighted and a plain sum like the beauty pass, the others keep the
+// value of the sample nearest to the pixel center in the first slot
+#[derive(Clone, Debug)]
+pub(crate) struct PixelSum {
+    color: Color,
+    plain: Color,
+    weight: f64,
+    samples: u32,
+    // squared distance of the nearest sample to the pixel center
+    nearest: f64,
+    passes: Vec<(Color, Color)>
+}
+
+impl PixelSum {
+    pub(crate) fn new(pass_count: usize) -> Self {
+        return Self {
+            color: Color::new_zero(),
+            plain: Color::new_zero(),
+            weight: 0.0,
+            samples: 0,
+            nearest: f64::INFINITY,
+            passes: vec![(Color::new_zero(), Color::new_zero()); pass_count]
+        };
+    }
+
+    // distance2 is the squared distance of the sample to the pixel center
+    pub(crate) fn add(&mut self, passes: &[Pass], weight: f64, distance2: f64,
+                      color: &Color, sample: &PassSample) {
+        add_weighted(&mut self.color, color, weight);
+        add_weighted(&mut self.plain, color, 1.0);
+        self.weight += weight;
+        self.samples += 1;
+
+        let is_nearest = distance2 < self.nearest;
+        if is_nearest {
+            self.nearest = distance2;
+        }
+        for (sum, pass) in self.passes.iter_mut().zip(passes.iter()) {
+            let value = pass.value(color, sample);
+            if pass.is_filtered() {
+                add_weighted(&mut sum.0, &value, weight);
+                add_weighted(&mut sum.1, &value, 1.0);
+            } else if is_nearest {
+                sum.0 = value;
+            }
+        }
+    }
+
+    // adds the samples of another round of the same pixel
+    fn merge(&mut self, passes: &[Pass], other: &PixelSum) {
+        add_weighted(&mut self.color, &other.color, 1.0);
+        add_weighted(&mut self.plain, &other.plain, 1.0);
+        self.weight += other.weight;
+        self.samples += other.samples;
+
+        let is_nearest = other.nearest < self.nearest;
+        if is_nearest {
+            self.nearest = other.nearest;
+        }
+        for ((sum, other), pass) in self.passes.iter_mut().zip(other.passes.iter()).zip(passes.iter()) {
+            if pass.is_filtered() {
+                add_weighted(&mut sum.0, &other.0, 1.0);
+                add_weighted(&mut sum.1, &other.1, 1.0);
+            } else if is_nearest {
+                sum.0 = other.0;
+            }
+        }
+    }
+
+    // the filtered color of the pixel, the value of every pass goes into values
+    fn resolve(&self, passes: &[Pass], values: &mut [Color]) -> Color {
+        if self.samples == 0 {
+            values.fill(Color::new_zero());
+            return Color::new_zero();
+        }
+
+        let mut weight = self.weight;
+        let mut color = self.color;
+        //negative lobes can cancel out all of the weight
+        let plain = weight <= 1e-9;
+        if plain {
+            weight = self.samples as f64;
+            color = self.plain;
+        }
+
+        let inv_weight = 1.0 / weight;
+        for ((value, sum), pass) in values.iter_mut().zip(self.passes.iter()).zip(passes.iter()) {
+            if !pass.is_filtered() {
+                *value = sum.0;
+                continue;
+            }
+
+            *value = if plain { sum.1 } else { sum.0 };
+            value.mul_self(inv_weight, inv_weight, inv_weight, inv_weight);
+        }
+
+        color.mul_self(inv_weight, inv_weight, inv_weight, inv_weight);
+        color.r = color.r.max(0.0);
+        color.g = color.g.max(0.0);
+        color.b = color.b.max(0.0);
+        color.a = color.a.clamp(0.0, 1.0);
+        return color;
+    }
+}
+
+// what a render has to keep the same to carry on from a checkpoint,
+// anything else would mix sums that do not belong together
+#[derive(Clone, Copy, Debug)]
+pub struct CheckpointSettings {
+    // the part of the image that is rendered, after clamping to the image
+    pub crop: Crop,
+    pub seed: u64,
+    pub filter: Filter,
+    pub sampling: Sampling,
+    pub integrator: Integrator,
+    pub max_depth: usize,
+    // samples per pixel of one round
+    pub round_samples: usize
+}
+
+impl CheckpointSettings {
+    // every setting by name as it is stored, in the order of the file
+    fn fields(&self) -> [(&'static str, Vec<u8>); 7] {
+        let crop = [self.crop.x, self.crop.y, self.crop.width, self.crop.height];
+        let (kind, params) = match self.filter {
+            Filter::Box { radius } => (0u8, [radius, 0.0, 0.0]),
+            Filter::Tent { radius } => (1, [radius, 0.0, 0.0]),
+            Filter::Gaussian { radius, alpha } => (2, [radius, alpha, 0.0]),
+            Filter::MitchellNetravali { radius, b, c } => (3, [radius, b, c])
+        };
+        let mut filter = vec![kind];
+        for v in params {
+            filter.extend_from_slice(&v.to_le_bytes());
+        }
+
+        return [
+            ("crop", crop.iter().flat_map(|v| v.to_le_bytes()).collect()),
+            ("seed", self.seed.to_le_bytes().to_vec()),
+            ("filter", filter),
+            ("sampling", vec![self.sampling as u8]),
+            ("integrator", vec![self.integrator as u8]),
+            ("depth", (self.max_depth as u64).to_le_bytes().to_vec()),
+            ("samples per round", (self.round_samples as u64).to_le_bytes().to_vec())
+        ];
+    }
+}
+
+// the sums of every sample a progressive render has taken so far, which
+// is what a checkpoint file holds. see RenderSettings::progressive
+pub struct Accumulation {
+    width: u32,
+    height: u32,
+    passes: Vec<Pass>,
+    settings: CheckpointSettings,
+    // finished rounds and the samples per pixel they added up to
+    rounds: u32,
+    samples: u32,
+    pixels: Vec<PixelSum>
+}
+
+impl Accumulation {
+    pub fn new(width: u32, height: u32, passes: &[Pass],
+               settings: CheckpointSettings) -> Result<Self> {
+        return Ok(Self {
+            width,
+            height,
+            passes: passes.to_vec(),
+            settings,
+            rounds: 0,
+            samples: 0,
+            pixels: vec![PixelSum::new(passes.len()); film::pixel_count(width, height)?]
+        });
+    }
+
+    pub fn width(&self) -> u32 {
+        return self.width;
+    }
+
+    pub fn height(&self) -> u32 {
+        return self.height;
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        return &self.passes;
+    }
+
+    pub fn rounds(&self) -> u32 {
+        return self.rounds;
+    }
+
+    // samples per pixel of the finished rounds
+    pub fn samples(&self) -> u32 {
+        return self.samples;
+    }
+
+    pub(crate) fn add_pixel(&mut self, x: u32, y: u32, sum: &PixelSum) {
+        self.pixels[y as usize * self.width as usize + x as usize].merge(&self.passes, sum);
+    }
+
+    pub(crate) fn finish_round(&mut self, samples: usize) {
+        self.rounds += 1;
+        self.samples += samples as u32;
+    }
+
+    // resolves one pixel into the film and its passes
+    pub(crate) fn write_pixel(&self, film: &mut Film, x: u32, y: u32) {
+        let mut values = vec![Color::new_zero(); self.passes.len()];
+        let color = self.pixels[y as usize * self.width as usize + x as usize].resolve(&self.passes, &mut values);
+        film.set_pixel(x, y, color);
+        for (i, value) in values.iter().enumerate() {
+            film.set_pass_pixel(i, x, y, *value);
+        }
+    }
+
+    pub(crate) fn write_film(&self, film: &mut Film) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.write_pixel(film, x, y);
+            }
+        }
+    }
+
+    // writes to a temporary file next to path first, so a render that is
+    // killed while saving keeps the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_os_string();
+        temp.push(".tmp");
+
+        let mut out = BufWriter::new(File::create(&temp)?);
+        out.write_all(MAGIC)?;
+        for v in [self.width, self.height, self.rounds, self.samples, self.passes.len() as u32] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        for pass in self.passes.iter() {
+            let index = Pass::ALL.iter().position(|p| p == pass).unwrap_or(0);
+            out.write_all(&[index as u8])?;
+        }
+        for (_, bytes) in self.settings.fields().iter() {
+            out.write_all(bytes)?;
+        }
+
+        for pixel in self.pixels.iter() {
+            out.write_all(&pixel.samples.to_le_bytes())?;
+            let mut values = vec![pixel.weight, pixel.nearest];
+            let mut colors = vec![pixel.color, pixel.plain];
+            for sum in pixel.passes.iter() {
+                colors.push(sum.0);
+                colors.push(sum.1);
+            }
+            for color in colors.iter() {
+                values.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+            }
+            for v in values {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+
+        out.flush()?;
+        drop(out);
+        fs::rename(&temp, path)?;
+        return Ok(());
+    }
+
+    // reads a checkpoint made for a width x height render with the given
+    // passes and settings. the header is checked before anything is
+    // allocated, so a damaged file is an error and not an out of memory abort
+    pub fn load<P: AsRef<Path>>(path: P, width: u32, height: u32, passes: &[Pass],
+                                settings: CheckpointSettings) -> Result<Self> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        read_bytes(&mut input, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Checkpoint("not a checkpoint file".to_string()));
+        }
+
+        let file_width = read_u32(&mut input)?;
+        let file_height = read_u32(&mut input)?;
+        let rounds = read_u32(&mut input)?;
+        let samples = read_u32(&mut input)?;
+        let pass_count = read_u32(&mut input)? as usize;
+        if pass_count > Pass::ALL.len() {
+            return Err(Error::Checkpoint("the checkpoint header is damaged".to_string()));
+        }
+
+        let mut file_passes = Vec::<Pass>::new();
+        for _ in 0..pass_count {
+            let mut index = [0u8];
+            read_bytes(&mut input, &mut index)?;
+            match Pass::ALL.get(index[0] as usize) {
+                Some(pass) => file_passes.push(*pass),
+                None => return Err(Error::Checkpoint(
+                    "the checkpoint has an unknown render pass".to_string()))
+            }
+        }
+
+        check(file_width, file_height, &file_passes, width, height, passes)?;
+
+        let mut settings_size = 0;
+        for (name, bytes) in settings.fields().iter() {
+            let mut file_bytes = vec![0u8; bytes.len()];
+            read_bytes(&mut input, &mut file_bytes)?;
+            if file_bytes != *bytes {
+                return Err(Error::Checkpoint(format!(
+                    "the checkpoint was rendered with a different {name}")));
+            }
+            settings_size += bytes.len();
+        }
+
+        let header_size = (MAGIC.len() + 5 * 4 + pass_count + settings_size) as u64;
+        let pixels_size = width as u64 * height as u64 * record_size(pass_count);
+        if file_size != header_size + pixels_size {
+            return Err(Error::Checkpoint(
+                "the checkpoint file is cut short or too long".to_string()));
+        }
+
+        let mut accumulation = Self::new(width, height, passes, settings)?;
+        accumulation.rounds = rounds;
+        accumulation.samples = samples;
+        for pixel in accumulation.pixels.iter_mut() {
+            pixel.samples = read_u32(&mut input)?;
+            pixel.weight = read_f64(&mut input)?;
+            pixel.nearest = read_f64(&mut input)?;
+            pixel.color = read_color(&mut input)?;
+            pixel.plain = read_color(&mut input)?;
+            for sum in pixel.passes.iter_mut() {
+                sum.0 = read_color(&mut input)?;
+                sum.1 = read_color(&mut input)?;
+            }
+        }
+
+        return Ok(accumulation);
+    }
+}
+
+// bytes one pixel takes in a checkpoint file: the sample count, the
+// weight, the nearest distance and two colors for the beauty and every pass
+fn record_size(pass_count: usize) -> u64 {
+    return 4 + 8 * (2 + 4 * 2 * (1 + pass_count as u64));
+}
+
+// whether the render settings still match what the checkpoint was made with
+fn check(file_width: u32, file_height: u32, file_passes: &[Pass],
+         width: u32, height: u32, passes: &[Pass]) -> Result<()> {
+    if file_width != width || file_height != height {
+        return Err(Error::Checkpoint(format!(
+            "the checkpoint is for a {file_width}x{file_height} image, not {width}x{height}")));
+    }
+
+    if file_passes != passes {
+        let names = file_passes.iter().map(|p| p.name()).collect::<Vec<&str>>();
+        return Err(Error::Checkpoint(format!(
+            "the checkpoint was rendered with the passes [{}]", names.join(", "))));
+    }
+
+    return Ok(());
+}
+
+fn read_bytes<R: Read>(input: &mut R, buf: &mut [u8]) -> Result<()> {
+    return input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Checkpoint("the checkpoint file is cut short".to_string()),
+        _ => Error::Io(err)
+    });
+}
+
+fn read_u32<R: Read>(input: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    read_bytes(input, &mut buf)?;
+    return Ok(u32::from_le_bytes(buf));
+}
+
+fn read_f64<R: Read>(input: &mut R) -> Result<f64> {
+    let mut buf = [0u8; 8];
+    read_bytes(input, &mut buf)?;
+    return Ok(f64::from_le_bytes(buf));
+}
+
+fn read_color<R: Read>(input: &mut R) -> Result<Color> {
+    let r = read_f64(input)?;
+    let g = read_f64(input)?;
+    let b = read_f64(input)?;
+    let a = read_f64(input)?;
+    return Ok(Color::new(r, g, b, a));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fksray::util::{temp_path, Vector2, Vector3};
+
+    const PASSES: [Pass; 2] = [Pass::Depth, Pass::Albedo];
+
+    fn settings() -> CheckpointSettings {
+        return CheckpointSettings {
+            crop: Crop::new(0, 0, 3, 2),
+            seed: 7,
+            filter: Filter::new_gaussian(),
+            sampling: Sampling::Stratified,
+            integrator: Integrator::PathTracer,
+            max_depth: 5,
+            round_samples: 2
+        };
+    }
+
+    // a 3x2 accumulation with one round of two samples in every pixel
+    fn accumulation() -> Accumulation {
+        let mut accumulation = Accumulation::new(3, 2, &PASSES, settings()).unwrap();
+        for y in 0..2 {
+            for x in 0..3 {
+                let mut sum = PixelSum::new(PASSES.len());
+                for i in 0..2 {
+                    let v = (x + 3 * y) as f64 + 0.25 * i as f64;
+                    let sample = PassSample {
+                        depth: 10.0 + v,
+                        normal: Vector3::new(0.0, 1.0, 0.0),
+                        albedo: Color::new(v, 0.5, 0.25, 1.0),
+                        uv: Vector2::new(0.0, 0.0),
+                        shape: 0,
+                        texture: -1,
+                        direct: Color::new_zero()
+                    };
+                    sum.add(&PASSES, 0.5 + 0.1 * i as f64, 0.1 * (2 - i) as f64,
+                            &Color::new(v, 2.0 * v, 0.5, 1.0), &sample);
+                }
+                accumulation.add_pixel(x, y, &sum);
+            }
+        }
+        accumulation.finish_round(2);
+        return accumulation;
+    }
+
+    fn film_of(accumulation: &Accumulation) -> Film {
+        let mut film = Film::new(accumulation.width(), accumulation.height()).unwrap();
+        film.set_passes(accumulation.passes()).unwrap();
+        accumulation.write_film(&mut film);
+        return film;
+    }
+
+    fn rgba(color: Color) -> [f64; 4] {
+        return [color.r, color.g, color.b, color.a];
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round-trip.ckpt");
+        let saved = accumulation();
+        saved.save(&path).unwrap();
+        let loaded = Accumulation::load(&path, 3, 2, &PASSES, settings()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.rounds(), 1);
+        assert_eq!(loaded.samples(), 2);
+        assert_eq!(loaded.passes(), &PASSES);
+
+        let (a, b) = (film_of(&saved), film_of(&loaded));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(rgba(a.pixel(x, y)), rgba(b.pixel(x, y)));
+                for pass in PASSES {
+                    assert_eq!(rgba(a.pass(pass).unwrap().pixel(x, y)),
+                               rgba(b.pass(pass).unwrap().pixel(x, y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_rejects_other_settings() {
+        let path = temp_path("settings.ckpt");
+        accumulation().save(&path).unwrap();
+
+        let mut other = settings();
+        other.seed = 8;
+        let seed = Accumulation::load(&path, 3, 2, &PASSES, other);
+        let size = Accumulation::load(&path, 4, 2, &PASSES, settings());
+        let passes = Accumulation::load(&path, 3, 2, &[Pass::Depth], settings());
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(seed, Err(Error::Checkpoint(_))));
+        assert!(matches!(size, Err(Error::Checkpoint(_))));
+        assert!(matches!(passes, Err(Error::Checkpoint(_))));
+    }
+
+    #[test]
+    fn load_rejects_damaged_files() {
+        let path = temp_path("damaged.ckpt");
+        accumulation().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut damaged = Vec::<Vec<u8>>::new();
+        //wrong magic
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        damaged.push(magic);
+        //a pass count far larger than there are passes
+        let mut pass_count = bytes.clone();
+        pass_count[MAGIC.len() + 16..MAGIC.len() + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        damaged.push(pass_count);
+        //an unknown pass
+        let mut pass = bytes.clone();
+        pass[MAGIC.len() + 20] = 200;
+        damaged.push(pass);
+        //cut short in the header and in the pixels, and too long
+        damaged.push(bytes[..12].to_vec());
+        damaged.push(bytes[..bytes.len() - 1].to_vec());
+        let mut long = bytes.clone();
+        long.push(0);
+        damaged.push(long);
+
+        for file in damaged {
+            fs::write(&path, &file).unwrap();
+            let result = Accumulation::load(&path, 3, 2, &PASSES, settings());
+            assert!(matches!(result, Err(Error::Checkpoint(_))), "{} bytes", file.len());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
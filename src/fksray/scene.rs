@@ -0,0 +1,635 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::error::{Error, Result, Warning};
+use super::camera::{Camera, Projection};
+use super::environment::Environment;
+use super::light::Light;
+use super::material::Material;
+use super::util::{Color, Vector2, Vector3, World};
+
+// json scene description, every field except the geometry is optional:
+//
+// {
+//     "camera": { "pos": [0, 60, 0], "target": [0, 0, 100], "fov": 100,
+//                 "aperture": 2, "focus_distance": 120, "projection": "perspective" },
+//     "textures": { "tiles": "test.jpg" },
+//     "materials": { "gold": { "type": "metal", "color": "#FFD280", "roughness": 0.2 },
+//                    "window": { "type": "glass", "ior": 1.5 } },
+//     "triangles": [ { "points": [[..], [..], [..]], "color": "#FF0000" } ],
+//     "planes": [ { "points": [[..], [..], [..], [..]],
+//                   "colors": ["#CFAB23", "#FF0000", "#00FF00", "#0000FF"],
+//                   "reflect": [0.5, 0.5, 0.5, 0.5], "texture": "tiles" } ],
+//     "spheres": [ { "center": [0, -40, 120], "radius": 30, "color": "#FFFFFF",
+//                    "reflect": 0.5, "texture": "tiles" } ],
+//     "meshes": [ { "file": "teapot.obj", "pos": [0, -70, 100], "scale": 10,
+//                   "material": "gold" } ],
+//     "point_lights": [ { "pos": [0, 50, 100], "color": "#FF0000", "lumen": 15000 } ],
+//     "lights": [ { "type": "quad", "points": [[..], [..], [..]], "power": 5000 },
+//                 { "type": "sphere", "center": [0, 50, 100], "radius": 5,
+//                   "color": "#FFE0C0", "power": 2000 },
+//                 { "type": "sun", "dir": [1, -2, 1], "angle": 0.53, "irradiance": 1000 },
+//                 { "type": "spot", "pos": [0, 60, 100], "dir": [0, -1, 0], "inner": 20,
+//                   "outer": 30, "falloff": 1, "intensity": 15000 } ],
+//     "environment": { "type": "map", "file": "sky.hdr", "intensity": 1, "rotation": 90 },
+//     "light_samples": 16
+// }
+//
+// texture, mesh and environment map paths are relative to the scene file. a shape with a
+// material ignores its reflect values. a quad light is the corner and its
+// two neighbours, see Light for the units. textures and materials are
+// numbered in the order of their names, which is what the texture_id pass shows
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default)]
+    //sorted so the ids follow the names and stay the same between runs
+    textures: BTreeMap<String, String>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    triangles: Vec<FaceDesc>,
+    #[serde(default)]
+    planes: Vec<FaceDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    point_lights: Vec<PointLightDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    light_samples: Option<usize>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    #[serde(default)]
+    pos: [f64; 3],
+    // either euler angles in degrees or a target to look at
+    #[serde(default)]
+    rot: Option<[f64; 3]>,
+    #[serde(default)]
+    target: Option<[f64; 3]>,
+    #[serde(default = "CameraDesc::default_up")]
+    up: [f64; 3],
+    // vertical field of view in degrees
+    #[serde(default = "CameraDesc::default_fov")]
+    fov: f64,
+    // lens radius for depth of field, focus_distance defaults to the
+    // distance to the target
+    #[serde(default)]
+    aperture: f64,
+    #[serde(default)]
+    focus_distance: Option<f64>,
+    // perspective, orthographic, fisheye or equirectangular,
+    // orthographic also needs ortho_height
+    #[serde(default)]
+    projection: Option<String>,
+    #[serde(default)]
+    ortho_height: Option<f64>
+}
+
+impl CameraDesc {
+    fn default_up() -> [f64; 3] {
+        return [0.0, 1.0, 0.0];
+    }
+
+    fn default_fov() -> f64 {
+        return 90.0;
+    }
+
+    fn camera(&self) -> Result<Camera> {
+        let pos = vector3(&self.pos);
+        let mut camera = match (&self.rot, &self.target) {
+            (Some(_), Some(_)) => return Err(Error::Scene(
+                "the camera takes either rot or target, not both".to_string())),
+            (None, Some(target)) => Camera::look_at(pos, vector3(target),
+                                                    vector3(&self.up), self.fov)?,
+            (rot, None) => Camera::from_euler(pos, vector3(&rot.unwrap_or([0.0; 3])),
+                                              self.fov)
+        };
+
+        if self.aperture < 0.0 {
+            return Err(Error::Scene("the camera aperture can not be negative".to_string()));
+        }
+
+        let focus_distance = match self.focus_distance {
+            Some(focus_distance) => focus_distance,
+            None if self.aperture > 0.0 && self.target.is_none() => return Err(Error::Scene(
+                "a camera with an aperture needs a focus_distance or a target".to_string())),
+            None => camera.focus_distance
+        };
+        if focus_distance <= 0.0 {
+            return Err(Error::Scene("the camera focus_distance must be positive".to_string()));
+        }
+
+        camera.set_lens(self.aperture, focus_distance);
+
+        if let Some(projection) = &self.projection {
+            camera.projection = Projection::from_name(projection, self.ortho_height)?;
+        }
+
+        camera.validate()?;
+        return Ok(camera);
+    }
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        return Self {
+            pos: [0.0; 3],
+            rot: None,
+            target: None,
+            up: Self::default_up(),
+            fov: Self::default_fov(),
+            aperture: 0.0,
+            focus_distance: None,
+            projection: None,
+            ortho_height: None
+        };
+    }
+}
+
+// a triangle (3 vertices) or a plane (4 vertices)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceDesc {
+    points: Vec<[f64; 3]>,
+    #[serde(default)]
+    uvs: Option<Vec<[f64; 2]>>,
+    // one color for every vertex, overridden by colors
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    colors: Option<Vec<String>>,
+    #[serde(default)]
+    reflect: Option<Vec<f64>>,
+    #[serde(default)]
+    texture: Option<String>,
+    #[serde(default)]
+    material: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f64; 3],
+    radius: f64,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    reflect: f64,
+    #[serde(default)]
+    texture: Option<String>,
+    #[serde(default)]
+    material: Option<String>
+}
+
+// an .obj file, see World::load_obj
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: String,
+    #[serde(default)]
+    pos: [f64; 3],
+    #[serde(default = "MeshDesc::default_scale")]
+    scale: f64,
+    #[serde(default)]
+    material: Option<String>
+}
+
+impl MeshDesc {
+    fn default_scale() -> f64 {
+        return 1.0;
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDesc {
+    pos: [f64; 3],
+    #[serde(default)]
+    color: Option<String>,
+    lumen: f64
+}
+
+// see Light
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LightDesc {
+    Quad {
+        points: [[f64; 3]; 3],
+        #[serde(default)]
+        color: Option<String>,
+        power: f64
+    },
+    Triangle {
+        points: [[f64; 3]; 3],
+        #[serde(default)]
+        color: Option<String>,
+        power: f64
+    },
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        #[serde(default)]
+        color: Option<String>,
+        power: f64
+    },
+    Sun {
+        dir: [f64; 3],
+        // angular diameter in degrees, 0 for hard shadows
+        #[serde(default)]
+        angle: f64,
+        #[serde(default)]
+        color: Option<String>,
+        irradiance: f64
+    },
+    Spot {
+        pos: [f64; 3],
+        dir: [f64; 3],
+        // in degrees from the axis
+        inner: f64,
+        outer: f64,
+        #[serde(default = "LightDesc::default_falloff")]
+        falloff: f64,
+        #[serde(default)]
+        color: Option<String>,
+        intensity: f64
+    }
+}
+
+impl LightDesc {
+    fn default_falloff() -> f64 {
+        return 1.0;
+    }
+
+    fn light(&self) -> Result<Light> {
+        return Ok(match self {
+            LightDesc::Quad { points: p, color: c, power } =>
+                Light::new_quad(vector3(&p[0]), vector3(&p[1]), vector3(&p[2]),
+                                color(c)?, *power),
+            LightDesc::Triangle { points: p, color: c, power } =>
+                Light::new_triangle(vector3(&p[0]), vector3(&p[1]), vector3(&p[2]),
+                                    color(c)?, *power),
+            LightDesc::Sphere { center, radius, color: c, power } =>
+                Light::new_sphere(vector3(center), *radius, color(c)?, *power),
+            LightDesc::Sun { dir, angle, color: c, irradiance } =>
+                Light::new_sun(vector3(dir), *angle, color(c)?, *irradiance),
+            LightDesc::Spot { pos, dir, inner, outer, falloff, color: c, intensity } =>
+                Light::new_spot(vector3(pos), vector3(dir), *inner, *outer, *falloff,
+                                color(c)?, *intensity)
+        });
+    }
+}
+
+// see Environment, the intensity scales every color
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Constant {
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default = "EnvironmentDesc::default_intensity")]
+        intensity: f64
+    },
+    Gradient {
+        zenith: String,
+        horizon: String,
+        #[serde(default)]
+        ground: Option<String>,
+        #[serde(default = "EnvironmentDesc::default_intensity")]
+        intensity: f64
+    },
+    Map {
+        file: String,
+        #[serde(default = "EnvironmentDesc::default_intensity")]
+        intensity: f64,
+        // degrees around the up axis
+        #[serde(default)]
+        rotation: f64
+    }
+}
+
+impl EnvironmentDesc {
+    fn default_intensity() -> f64 {
+        return 1.0;
+    }
+
+    fn environment(&self, dir: &Path) -> Result<Environment> {
+        let scale = |c: Color, s: f64| Color::new(c.r * s, c.g * s, c.b * s, 1.0);
+        return match self {
+            EnvironmentDesc::Constant { color: c, intensity } =>
+                Ok(Environment::new_constant(scale(color(c)?, *intensity))),
+            EnvironmentDesc::Gradient { zenith, horizon, ground, intensity } => {
+                //without a ground the horizon goes all the way down
+                let horizon = Color::new_string(horizon)?;
+                let ground = match ground {
+                    Some(ground) => Color::new_string(ground)?,
+                    None => horizon
+                };
+                Ok(Environment::new_gradient(scale(Color::new_string(zenith)?, *intensity),
+                                             scale(horizon, *intensity),
+                                             scale(ground, *intensity)))
+            },
+            EnvironmentDesc::Map { file, intensity, rotation } =>
+                Environment::new_map(dir.join(file), *intensity, *rotation)
+        };
+    }
+}
+
+// see Material, every field but the type is optional
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDesc {
+    Diffuse {
+        #[serde(default)]
+        color: Option<String>
+    },
+    Metal {
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default)]
+        roughness: f64
+    },
+    Glass {
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default = "MaterialDesc::default_ior")]
+        ior: f64,
+        #[serde(default)]
+        roughness: f64
+    },
+    Plastic {
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default = "MaterialDesc::default_ior")]
+        ior: f64,
+        #[serde(default)]
+        roughness: f64
+    }
+}
+
+impl MaterialDesc {
+    fn default_ior() -> f64 {
+        return 1.5;
+    }
+
+    fn material(&self) -> Result<Material> {
+        return Ok(match self {
+            MaterialDesc::Diffuse { color: c } =>
+                Material::Diffuse { color: color(c)? },
+            MaterialDesc::Metal { color: c, roughness } =>
+                Material::Metal { color: color(c)?, roughness: *roughness },
+            MaterialDesc::Glass { color: c, ior, roughness } =>
+                Material::Glass { color: color(c)?, ior: *ior, roughness: *roughness },
+            MaterialDesc::Plastic { color: c, ior, roughness } =>
+                Material::Plastic { color: color(c)?, ior: *ior, roughness: *roughness }
+        });
+    }
+}
+
+fn vector3(v: &[f64; 3]) -> Vector3 {
+    return Vector3::new(v[0], v[1], v[2]);
+}
+
+fn color(s: &Option<String>) -> Result<Color> {
+    return match s {
+        Some(s) => Color::new_string(s),
+        None => Ok(Color::new(1.0, 1.0, 1.0, 1.0))
+    };
+}
+
+fn texture(name: &Option<String>, textures: &HashMap<String, isize>) -> Result<isize> {
+    return match name {
+        Some(name) => textures.get(name).copied()
+            .ok_or_else(|| Error::Scene(format!("unknown texture \"{name}\""))),
+        None => Ok(-1)
+    };
+}
+
+fn material(name: &Option<String>, materials: &HashMap<String, isize>) -> Result<isize> {
+    return match name {
+        Some(name) => materials.get(name).copied()
+            .ok_or_else(|| Error::Scene(format!("unknown material \"{name}\""))),
+        None => Ok(-1)
+    };
+}
+
+impl FaceDesc {
+    // the default uvs follow the vertex order of World::create_plane
+    const DEFAULT_UVS: [[f64; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+
+    // checks that every per vertex list has one entry for each vertex
+    fn check(&self, vertices: usize, kind: &str) -> Result<()> {
+        let lens = [
+            Some(self.points.len()),
+            self.uvs.as_ref().map(|v| v.len()),
+            self.colors.as_ref().map(|v| v.len()),
+            self.reflect.as_ref().map(|v| v.len())
+        ];
+
+        if lens.iter().flatten().any(|len| *len != vertices) {
+            return Err(Error::Scene(
+                format!("a {kind} needs exactly {vertices} entries per vertex list")));
+        }
+
+        return Ok(());
+    }
+
+    fn point(&self, i: usize) -> Vector3 {
+        return vector3(&self.points[i]);
+    }
+
+    fn uv(&self, i: usize) -> Vector2 {
+        let uv = match &self.uvs {
+            Some(uvs) => uvs[i],
+            None => Self::DEFAULT_UVS[i]
+        };
+
+        return Vector2::new(uv[0], uv[1]);
+    }
+
+    fn color(&self, i: usize) -> Result<Color> {
+        return match &self.colors {
+            Some(colors) => Color::new_string(&colors[i]),
+            None => color(&self.color)
+        };
+    }
+
+    fn reflect(&self, i: usize) -> f64 {
+        return match &self.reflect {
+            Some(reflect) => reflect[i],
+            None => 0.0
+        };
+    }
+
+    fn texture(&self, textures: &HashMap<String, isize>) -> Result<isize> {
+        return texture(&self.texture, textures);
+    }
+}
+
+impl World {
+    // builds a world and its camera from a json scene file, along with
+    // anything the meshes had to work around
+    pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<(World, Camera, Vec<Warning>)> {
+        let path = path.as_ref();
+        let scene: SceneFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut world = World::new();
+
+        let mut textures = HashMap::<String, isize>::new();
+        for (name, file) in scene.textures.iter() {
+            let id = world.create_texture(dir.join(file))?;
+            textures.insert(name.clone(), id);
+        }
+
+        let mut materials = HashMap::<String, isize>::new();
+        for (name, desc) in scene.materials.iter() {
+            let id = world.create_material(desc.material()?);
+            materials.insert(name.clone(), id);
+        }
+
+        for tri in scene.triangles.iter() {
+            tri.check(3, "triangle")?;
+            world.create_triangle(
+                tri.point(0), tri.point(1), tri.point(2),
+                tri.uv(0), tri.uv(1), tri.uv(2),
+                tri.color(0)?, tri.color(1)?, tri.color(2)?,
+                [tri.reflect(0), tri.reflect(1), tri.reflect(2)],
+                tri.texture(&textures)?, material(&tri.material, &materials)?);
+        }
+
+        for plane in scene.planes.iter() {
+            plane.check(4, "plane")?;
+            world.create_plane(
+                plane.point(0), plane.point(1), plane.point(2), plane.point(3),
+                plane.uv(0), plane.uv(1), plane.uv(2), plane.uv(3),
+                plane.color(0)?, plane.color(1)?, plane.color(2)?, plane.color(3)?,
+                [plane.reflect(0), plane.reflect(1), plane.reflect(2), plane.reflect(3)],
+                plane.texture(&textures)?, material(&plane.material, &materials)?);
+        }
+
+        for sphere in scene.spheres.iter() {
+            if sphere.radius.is_nan() || sphere.radius <= 0.0 {
+                return Err(Error::Scene("a sphere needs a positive radius".to_string()));
+            }
+
+            world.create_sphere(vector3(&sphere.center), sphere.radius,
+                                color(&sphere.color)?, sphere.reflect,
+                                texture(&sphere.texture, &textures)?,
+                                material(&sphere.material, &materials)?);
+        }
+
+        let mut warnings = Vec::<Warning>::new();
+        for mesh in scene.meshes.iter() {
+            let (_, mesh_warnings) = world.load_obj(dir.join(&mesh.file), vector3(&mesh.pos),
+                                                    mesh.scale,
+                                                    material(&mesh.material, &materials)?)?;
+            warnings.extend(mesh_warnings);
+        }
+
+        for light in scene.point_lights.iter() {
+            world.create_point_light(vector3(&light.pos), color(&light.color)?, light.lumen);
+        }
+
+        for light in scene.lights.iter() {
+            world.create_light(light.light()?);
+        }
+
+        if let Some(environment) = &scene.environment {
+            world.set_environment(Some(environment.environment(dir)?));
+        }
+
+        if let Some(samples) = scene.light_samples {
+            if samples == 0 {
+                return Err(Error::Scene("light_samples must be at least 1".to_string()));
+            }
+            world.set_light_samples(samples);
+        }
+
+        return Ok((world, scene.camera.camera()?, warnings));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fksray::util::temp_path;
+
+    fn load(name: &str, json: &str) -> Result<(World, Camera, Vec<Warning>)> {
+        let path = temp_path(&format!("{name}.json"));
+        fs::write(&path, json).unwrap();
+        let result = World::load_scene(&path);
+        fs::remove_file(&path).unwrap();
+        return result;
+    }
+
+    #[test]
+    fn valid_scene() {
+        let (world, camera, warnings) = load("valid", r##"{
+            "camera": { "pos": [0, 5, -20], "target": [0, 0, 0], "fov": 50 },
+            "materials": { "gold": { "type": "metal", "color": "#FFD280", "roughness": 0.2 },
+                           "floor": { "type": "diffuse" } },
+            "planes": [ { "points": [[-5, 0, -5], [5, 0, -5], [-5, 0, 5], [5, 0, 5]],
+                          "material": "floor" } ],
+            "spheres": [ { "center": [0, 1, 0], "radius": 1, "material": "gold" } ],
+            "lights": [ { "type": "sphere", "center": [0, 10, 0], "radius": 1, "power": 100 } ],
+            "environment": { "type": "constant", "color": "#202020" },
+            "light_samples": 4
+        }"##).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!((camera.pos.y, camera.fov), (5.0, 50.0));
+        assert_eq!(world.shapes.len(), 3);
+        assert_eq!(world.lights.len(), 1);
+        assert!(world.environment.is_some());
+        assert_eq!(world.light_samples, 4);
+
+        //materials are numbered by name
+        assert_eq!(world.materials.len(), 2);
+        assert!(matches!(world.materials[0], Material::Diffuse { .. }));
+        assert!(matches!(world.materials[1], Material::Metal { roughness, .. } if roughness == 0.2));
+        assert_eq!(world.shapes[0].material(), 0);
+        assert_eq!(world.shapes[2].material(), 1);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        for json in [
+            r#"{ "sphere": [] }"#,
+            r#"{ "camera": { "position": [0, 0, 0] } }"#,
+            r##"{ "spheres": [ { "center": [0, 0, 0], "radius": 1, "colour": "#FFFFFF" } ] }"##,
+            r#"{ "materials": { "a": { "type": "diffuse", "roughness": 0.5 } } }"#,
+            r#"{ "materials": { "a": { "type": "chrome" } } }"#,
+            r#"{ "lights": [ { "type": "quad", "points": [[0, 0, 0], [1, 0, 0], [0, 0, 1]],
+                               "power": 1, "radius": 1 } ] }"#
+        ] {
+            assert!(matches!(load("unknown-field", json), Err(Error::Json(_))), "{json}");
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        let result = load("unknown-texture",
+            r#"{ "spheres": [ { "center": [0, 0, 0], "radius": 1, "texture": "tiles" } ] }"#);
+        assert!(matches!(result, Err(Error::Scene(msg)) if msg.contains("\"tiles\"")));
+
+        let result = load("unknown-material", r#"{
+            "materials": { "gold": { "type": "metal" } },
+            "triangles": [ { "points": [[0, 0, 0], [1, 0, 0], [0, 0, 1]], "material": "glod" } ]
+        }"#);
+        assert!(matches!(result, Err(Error::Scene(msg)) if msg.contains("\"glod\"")));
+    }
+}
@@ -0,0 +1,261 @@
+use std::f64::consts::PI;
+
+use super::util::{Color, Vector2, Vector3};
+
+// what shading needs to know about a point on a shape
+#[derive(Clone, Copy, Debug)]
+pub struct Interaction {
+    pub pos: Vector3,
+    // geometric normal, it may face either side of the surface
+    pub face_norm: Vector3,
+    // unit normal used for lighting, ray_trace flips it to the side of
+    // face_norm the ray comes from
+    pub norm: Vector3
+}
+
+// anything that can be put in a World and traced. u and v are whatever
+// parametrisation the shape likes (barycentric coordinates for triangles),
+// intersect hands them out and every other method takes them back
+pub trait Shape: Send + Sync {
+    // axis aligned box around the shape as (min, max)
+    fn bounds(&self) -> (Vector3, Vector3);
+
+    // closest hit along the ray in front of ori as (t, u, v), dir does
+    // not have to be normalized
+    fn intersect(&self, ori: &Vector3, dir: &Vector3) -> Option<(f64, f64, f64)>;
+
+    fn interaction(&self, u: f64, v: f64) -> Interaction;
+
+    // texture coordinates
+    fn uv(&self, u: f64, v: f64) -> Vector2;
+
+    fn color(&self, u: f64, v: f64) -> Color;
+
+    // how much of the reflected ray is mixed in, from 0 to 1
+    fn reflect(&self, u: f64, v: f64) -> f64;
+
+    // texture id or -1
+    fn texture(&self) -> isize;
+
+    // material id from World::create_material, -1 shades with color
+    // and reflect instead
+    fn material(&self) -> isize {
+        return -1;
+    }
+
+    // checked by World::compute before the bvh is built
+    fn is_valid(&self) -> bool {
+        let (min, max) = self.bounds();
+        return min.is_finite() && max.is_finite();
+    }
+}
+
+pub(crate) struct Triangle {
+    pub(crate) p1: Vector3,
+    pub(crate) p2: Vector3,
+    pub(crate) p3: Vector3,
+    pub(crate) e1: Vector3,
+    pub(crate) e2: Vector3,
+    pub(crate) uv1: Vector2,
+    pub(crate) uv2: Vector2,
+    pub(crate) uv3: Vector2,
+    pub(crate) c1: Color,
+    pub(crate) c2: Color,
+    pub(crate) c3: Color,
+    pub(crate) reflect: [f64; 3],
+    pub(crate) norm: Vector3,
+    pub(crate) n1: Vector3,
+    pub(crate) n2: Vector3,
+    pub(crate) n3: Vector3,
+    pub(crate) tex: isize,
+    pub(crate) mat: isize
+}
+
+impl Triangle {
+    const EPSILON: f64 = 0.0001;
+}
+
+impl Shape for Triangle {
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let min = Vector3 {
+            x: f64::min(self.p1.x, f64::min(self.p2.x, self.p3.x)),
+            y: f64::min(self.p1.y, f64::min(self.p2.y, self.p3.y)),
+            z: f64::min(self.p1.z, f64::min(self.p2.z, self.p3.z)),
+        };
+        let max = Vector3 {
+            x: f64::max(self.p1.x, f64::max(self.p2.x, self.p3.x)),
+            y: f64::max(self.p1.y, f64::max(self.p2.y, self.p3.y)),
+            z: f64::max(self.p1.z, f64::max(self.p2.z, self.p3.z)),
+        };
+        return (min, max);
+    }
+
+    //moller trumbore, u and v are the weights of p2 and p3
+    fn intersect(&self, ori: &Vector3, dir: &Vector3) -> Option<(f64, f64, f64)> {
+        let pvec = dir.cross(&self.e2);
+        let det = pvec.dot(&self.e1);
+
+        if det.abs() < Self::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+
+        let tvec = ori.sub(&self.p1);
+
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&self.e1);
+        let v = dir.dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = self.e2.dot(&qvec) * inv_det;
+        return if t < 0.0 || t.abs() < Self::EPSILON {
+            None
+        } else {
+            Some((t, u, v))
+        }
+    }
+
+    fn interaction(&self, u: f64, v: f64) -> Interaction {
+        let subuv = 1.0 - u - v;
+        let pos = Vector3::new(
+            self.p1.x * subuv + self.p2.x * u + self.p3.x * v,
+            self.p1.y * subuv + self.p2.y * u + self.p3.y * v,
+            self.p1.z * subuv + self.p2.z * u + self.p3.z * v);
+
+        //interpolated vertex normal
+        let mut norm = Vector3::new(
+            self.n1.x * subuv + self.n2.x * u + self.n3.x * v,
+            self.n1.y * subuv + self.n2.y * u + self.n3.y * v,
+            self.n1.z * subuv + self.n2.z * u + self.n3.z * v);
+        norm.normalize_dis();
+
+        return Interaction { pos, face_norm: self.norm, norm };
+    }
+
+    fn uv(&self, u: f64, v: f64) -> Vector2 {
+        let subuv = 1.0 - u - v;
+        return Vector2::new(
+            self.uv1.x * subuv + self.uv2.x * u + self.uv3.x * v,
+            self.uv1.y * subuv + self.uv2.y * u + self.uv3.y * v);
+    }
+
+    fn color(&self, u: f64, v: f64) -> Color {
+        let subuv = 1.0 - u - v;
+        return Color::new(
+            self.c1.r * subuv + self.c2.r * u + self.c3.r * v,
+            self.c1.g * subuv + self.c2.g * u + self.c3.g * v,
+            self.c1.b * subuv + self.c2.b * u + self.c3.b * v,
+            self.c1.a * subuv + self.c2.a * u + self.c3.a * v,);
+    }
+
+    fn reflect(&self, u: f64, v: f64) -> f64 {
+        return
+            self.reflect[0] * (1.0 - u - v) +
+            self.reflect[1] * u +
+            self.reflect[2] * v;
+    }
+
+    fn texture(&self) -> isize {
+        return self.tex;
+    }
+
+    fn material(&self) -> isize {
+        return self.mat;
+    }
+}
+
+pub(crate) struct Sphere {
+    pub(crate) center: Vector3,
+    pub(crate) radius: f64,
+    pub(crate) color: Color,
+    pub(crate) reflect: f64,
+    pub(crate) tex: isize,
+    pub(crate) mat: isize
+}
+
+impl Sphere {
+    const EPSILON: f64 = 0.0001;
+
+    //outward unit normal at the spherical uv
+    fn normal(&self, u: f64, v: f64) -> Vector3 {
+        let (sin_lon, cos_lon) = ((u - 0.5) * 2.0 * PI).sin_cos();
+        let (sin_lat, cos_lat) = ((v - 0.5) * PI).sin_cos();
+        return Vector3::new(cos_lat * cos_lon, sin_lat, cos_lat * sin_lon);
+    }
+}
+
+impl Shape for Sphere {
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        return (self.center.sub(&r), self.center.add(&r));
+    }
+
+    //u and v are the spherical uv of the hit
+    fn intersect(&self, ori: &Vector3, dir: &Vector3) -> Option<(f64, f64, f64)> {
+        let oc = ori.sub(&self.center);
+        let a = dir.dot(dir);
+        let b = oc.dot(dir);
+        let c = oc.dot(&oc) - self.radius * self.radius;
+
+        let disc = b * b - a * c;
+        if disc < 0.0 {
+            return None;
+        }
+
+        //near side first, the far side when the ray starts inside
+        let sqrt_disc = disc.sqrt();
+        let mut t = (-b - sqrt_disc) / a;
+        if t < Self::EPSILON {
+            t = (-b + sqrt_disc) / a;
+            if t < Self::EPSILON {
+                return None;
+            }
+        }
+
+        let mut d = oc.add(&dir.scale(t));
+        d.normalize_dis();
+        let u = 0.5 + f64::atan2(d.z, d.x) / (2.0 * PI);
+        let v = 0.5 + f64::asin(d.y.clamp(-1.0, 1.0)) / PI;
+        return Some((t, u, v));
+    }
+
+    fn interaction(&self, u: f64, v: f64) -> Interaction {
+        let norm = self.normal(u, v);
+        return Interaction {
+            pos: self.center.add(&norm.scale(self.radius)),
+            face_norm: norm,
+            norm
+        };
+    }
+
+    fn uv(&self, u: f64, v: f64) -> Vector2 {
+        return Vector2::new(u, v);
+    }
+
+    fn color(&self, _u: f64, _v: f64) -> Color {
+        return self.color;
+    }
+
+    fn reflect(&self, _u: f64, _v: f64) -> f64 {
+        return self.reflect;
+    }
+
+    fn texture(&self) -> isize {
+        return self.tex;
+    }
+
+    fn material(&self) -> isize {
+        return self.mat;
+    }
+
+    fn is_valid(&self) -> bool {
+        return self.center.is_finite() && self.radius.is_finite() && self.radius > 0.0;
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::Cell;
+use std::fmt;
+use std::time::Duration;
+
+// what the rays of a render cost
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayCounts {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    // reflected, refracted and passed through rays and path tracer bounces
+    pub reflection_rays: u64,
+    // camera rays traced again for the render passes. they are not in
+    // rays() and their traversal is not counted, so the other counts
+    // stay the same with and without passes
+    pub pass_rays: u64,
+    pub node_visits: u64,
+    // ray against shape intersection tests, triangles and spheres alike
+    pub shape_tests: u64
+}
+
+impl RayCounts {
+    pub fn rays(&self) -> u64 {
+        return self.primary_rays + self.shadow_rays + self.reflection_rays;
+    }
+
+    pub fn add(&mut self, other: &RayCounts) {
+        self.primary_rays += other.primary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.reflection_rays += other.reflection_rays;
+        self.pass_rays += other.pass_rays;
+        self.node_visits += other.node_visits;
+        self.shape_tests += other.shape_tests;
+    }
+}
+
+//every worker counts for itself, the counts are collected with every tile
+thread_local! {
+    static COUNTS: Cell<RayCounts> = Cell::new(RayCounts::default());
+}
+
+pub(crate) fn count<F: FnOnce(&mut RayCounts)>(f: F) {
+    COUNTS.with(|counts| {
+        let mut c = counts.get();
+        f(&mut c);
+        counts.set(c);
+    });
+}
+
+// runs f without adding anything it counts
+pub(crate) fn uncounted<T, F: FnOnce() -> T>(f: F) -> T {
+    let counts = take_counts();
+    let result = f();
+    COUNTS.with(|c| c.set(counts));
+    return result;
+}
+
+// the counts of this thread since the last call
+pub(crate) fn take_counts() -> RayCounts {
+    return COUNTS.with(|counts| counts.take());
+}
+
+// how far a render is, handed to the ProgressObserver after every tile
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    // samples taken and to take over all pixels and rounds
+    pub done: u64,
+    pub total: u64,
+    // the round the tile belongs to, counting from 1
+    pub round: u32,
+    pub rounds: u32,
+    // since the render started, which is not when a resumed one began
+    pub elapsed: Duration,
+    pub rays: u64,
+    // samples a checkpoint already had
+    pub resumed: u64
+}
+
+impl Progress {
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+
+        return 100.0 * self.done as f64 / self.total as f64;
+    }
+
+    // time left at the speed of this run so far
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.done.saturating_sub(self.resumed);
+        if done == 0 {
+            return None;
+        }
+
+        let left = self.total.saturating_sub(self.done);
+        return Some(self.elapsed.mul_f64(left as f64 / done as f64));
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+
+        return self.rays as f64 / seconds;
+    }
+}
+
+// gets told how a render is going, see raytracer_with_progress. it is
+// called on the thread that started the render
+pub trait ProgressObserver {
+    fn progress(&mut self, progress: &Progress);
+}
+
+impl<F: FnMut(&Progress)> ProgressObserver for F {
+    fn progress(&mut self, progress: &Progress) {
+        self(progress);
+    }
+}
+
+// what a finished render did
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    pub counts: RayCounts,
+    // time of every phase in the order they ran
+    pub phases: Vec<(String, Duration)>
+}
+
+impl RenderStats {
+    pub fn add_phase(&mut self, name: &str, time: Duration) {
+        self.phases.push((name.to_string(), time));
+    }
+
+    pub fn phase(&self, name: &str) -> Option<Duration> {
+        return self.phases.iter().find(|(n, _)| n == name).map(|(_, time)| *time);
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.counts;
+        let seconds = self.phase("render").map_or(0.0, |time| time.as_secs_f64());
+        let per_second = if seconds > 0.0 { c.rays() as f64 / seconds } else { 0.0 };
+        let per_ray = if c.rays() > 0 { 1.0 / c.rays() as f64 } else { 0.0 };
+
+        write!(f, "rays: {} primary, {} shadow, {} reflection, {:.2}M rays/s",
+               c.primary_rays, c.shadow_rays, c.reflection_rays, per_second / 1e6)?;
+        if c.pass_rays > 0 {
+            write!(f, ", {} more for the passes", c.pass_rays)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "traversal: {} node visits, {} shape tests, {:.1} / {:.1} per ray",
+                 c.node_visits, c.shape_tests,
+                 c.node_visits as f64 * per_ray, c.shape_tests as f64 * per_ray)?;
+
+        let phases = self.phases.iter()
+            .map(|(name, time)| format!("{name} {:.3}s", time.as_secs_f64()))
+            .collect::<Vec<String>>();
+        return write!(f, "time: {}", phases.join(", "));
+    }
+}
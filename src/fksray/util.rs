@@ -1,5 +1,13 @@
 use std::collections::HashMap;
-use image::RgbImage;
+use std::path::Path;
+use image::RgbaImage;
+
+use super::bvh::{self, BvhReport, BvhSettings};
+use super::environment::Environment;
+use super::error::{Error, Result};
+use super::light::Light;
+use super::material::Material;
+use super::shape::{Shape, Sphere, Triangle};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Color {
@@ -34,21 +42,31 @@ impl Color {
         }
     }
 
-    pub fn new_string(s: &str) -> Color {
-        if s.len() != 7 && s.len() != 9 {
-            panic!("Invalid color string");
+    // parses #RRGGBB or #RRGGBBAA
+    pub fn new_string(s: &str) -> Result<Color> {
+        //from_str_radix alone would take a sign like "+F"
+        if (s.len() != 7 && s.len() != 9) || !s.starts_with('#') ||
+            !s[1..].bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidColor(s.to_string()));
         }
 
-        let r = Self::COLOR_MAX_INV * (u8::from_str_radix(&s[1..3], 16).unwrap() as f64);
-        let g = Self::COLOR_MAX_INV * (u8::from_str_radix(&s[3..5], 16).unwrap() as f64);
-        let b = Self::COLOR_MAX_INV * (u8::from_str_radix(&s[5..7], 16).unwrap() as f64);
+        let channel = |i: usize| -> Result<f64> {
+            return match u8::from_str_radix(&s[i..i + 2], 16) {
+                Ok(c) => Ok(Self::COLOR_MAX_INV * c as f64),
+                Err(_) => Err(Error::InvalidColor(s.to_string()))
+            };
+        };
+
+        let r = channel(1)?;
+        let g = channel(3)?;
+        let b = channel(5)?;
         
         let mut a = 1.0;
         if s.len() == 9 {
-            a = Self::COLOR_MAX_INV * (u8::from_str_radix(&s[7..9], 16).unwrap() as f64);
+            a = channel(7)?;
         }
         
-        return Color { r, g, b, a };
+        return Ok(Color { r, g, b, a });
     }
 
     pub fn mul_self(&mut self, r: f64, g: f64, b: f64, a: f64) {
@@ -62,7 +80,7 @@ impl Color {
 struct Texture {
     width: u32,
     height: u32,
-    img: RgbImage
+    img: RgbaImage
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -138,6 +156,10 @@ impl Vector3 {
         return dis2;
     }
 
+    pub fn is_finite(&self) -> bool {
+        return self.x.is_finite() && self.y.is_finite() && self.z.is_finite();
+    }
+
     pub fn distance2(&self) -> f64 {
         return self.x * self.x + self.y * self.y + self.z * self.z;
     }
@@ -164,6 +186,14 @@ impl Vector3 {
         }
     }
 
+    pub fn scale(&self, s: f64) -> Self {
+        return Self {
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+        }
+    }
+
     pub fn inverse(&self) -> Self {
         return Self {
             x: 1.0 / self.x,
@@ -185,148 +215,49 @@ impl Vector2 {
     }
 }
 
-pub(crate) struct Triangle {
-    pub(crate) p1: Vector3,
-    pub(crate) p2: Vector3,
-    pub(crate) p3: Vector3,
-    pub(crate) e1: Vector3,
-    pub(crate) e2: Vector3,
-    pub(crate) uv1: Vector2,
-    pub(crate) uv2: Vector2,
-    pub(crate) uv3: Vector2,
-    pub(crate) c1: Color,
-    pub(crate) c2: Color,
-    pub(crate) c3: Color,
-    pub(crate) reflect: [f64; 3],
-    pub(crate) mid: f64,
-    pub(crate) norm: Vector3,
-    pub(crate) norm_opp: Vector3,
-    pub(crate) tex: isize
-}
-
-impl Triangle {
-    const EPSILON: f64 = 0.0001;
-    
-    pub fn max_box(&self) -> Vector3 {
-        return Vector3 {
-            x: f64::max(self.p1.x, f64::max(self.p2.x, self.p3.x)),
-            y: f64::max(self.p1.y, f64::max(self.p2.y, self.p3.y)),
-            z: f64::max(self.p1.z, f64::max(self.p2.z, self.p3.z)),
-        };
-    }
-    
-    pub fn min_box(&self) -> Vector3 {
-        return Vector3 {
-            x: f64::min(self.p1.x, f64::min(self.p2.x, self.p3.x)),
-            y: f64::min(self.p1.y, f64::min(self.p2.y, self.p3.y)),
-            z: f64::min(self.p1.z, f64::min(self.p2.z, self.p3.z)),
-        };
-    }
-    
-    //returns t, u, and v
-    pub fn intersect(&self, ori: &Vector3, dir: &Vector3) -> (f64, f64, f64) {
-        let pvec = dir.cross(&self.e2);
-        let det = pvec.dot(&self.e1);
-
-        if det.abs() < Self::EPSILON {
-            return (-1.0, 0.0, 0.0);
-        }
-
-        let inv_det = 1.0 / det;
-        
-        let tvec = ori.sub(&self.p1);
-
-        let u = tvec.dot(&pvec) * inv_det;
-        if u < 0.0 || u > 1.0 {
-            return (-1.0, 0.0, 0.0);
-        }
-
-        let qvec = tvec.cross(&self.e1);
-        let v = dir.dot(&qvec) * inv_det;
-        if v < 0.0 || u + v > 1.0 {
-            return (-1.0, 0.0, 0.0);
-        }
-
-        let t = self.e2.dot(&qvec) * inv_det;
-        return if t < 0.0 || t.abs() < Self::EPSILON {
-            (-1.0, 0.0, 0.0)
-        } else {
-            (t, u, v)
-        }
-    }
-
-    pub fn obtain_uv(&self, u: f64, v: f64) -> Vector2 {
-        let subuv = 1.0 - u - v;
-        return Vector2::new(
-            self.uv1.x * subuv + self.uv2.x * u + self.uv3.x * v,
-            self.uv1.y * subuv + self.uv2.y * u + self.uv3.y * v);
-    }
-
-    pub fn obtain_reflect(&self, u: f64, v: f64) -> f64 {
-        return
-            self.reflect[0] * (1.0 - u - v) +
-            self.reflect[1] * u +
-            self.reflect[2] * v;
-    }
-
-    pub fn obtain_pos(&self, u: f64, v: f64) -> Vector3 {
-        let subuv = 1.0 - u - v;
-        return Vector3::new(
-            self.p1.x * subuv + self.p2.x * u + self.p3.x * v,
-            self.p1.y * subuv + self.p2.y * u + self.p3.y * v,
-            self.p1.z * subuv + self.p2.z * u + self.p3.z * v);
-    }
-    
-    pub fn obtain_color(&self, u: f64, v: f64) -> Color {
-        let subuv = 1.0 - u - v;
-        return Color::new(
-            self.c1.r * subuv + self.c2.r * u + self.c3.r * v,
-            self.c1.g * subuv + self.c2.g * u + self.c3.g * v,
-            self.c1.b * subuv + self.c2.b * u + self.c3.b * v,
-            self.c1.a * subuv + self.c2.a * u + self.c3.a * v,);
-    }
-}
-
 // structure:
-// when the bounding box is less than the triangles size
-// that index becomes the triangle's index
+// when left and right are -1 the bounding box is a leaf
+// holding the shapes start..start + count
 // 
 // otherwise, the bounding box should be composed of
 // two other bounding boxes
 //
-// the last bbox contains the root node.
+// the first bbox contains the root node.
 #[derive(Debug)]
 pub(crate) struct BoundingBox {
     pub(crate) m1: Vector3,
     pub(crate) m2: Vector3,
     pub(crate) left: isize,
-    pub(crate) right: isize
+    pub(crate) right: isize,
+    pub(crate) start: usize,
+    pub(crate) count: usize
 }
 
 impl BoundingBox {
-    pub fn intersect(&self, ori: &Vector3, norm: &Vector3) -> bool {
-        let norm_inv = norm.inverse();
-        
-        //slab test
+    // slab test against the ray limited to tmin..tmax, norm_inv is the
+    // inverse of the ray direction. returns the distance where the ray
+    // enters the box (tmin when it starts inside)
+    pub fn intersect(&self, ori: &Vector3, norm_inv: &Vector3,
+                     tmin: f64, tmax: f64) -> Option<f64> {
         //x
         let mut t1 = norm_inv.x * (self.m1.x - ori.x);
         let mut t2 = norm_inv.x * (self.m2.x - ori.x);
-        let mut tmin = t1.min(t2);
-        let mut tmax = t2.max(t1);
+        let mut tnear = tmin.max(t1.min(t2));
+        let mut tfar = tmax.min(t2.max(t1));
 
         //y
         t1 = norm_inv.y * (self.m1.y - ori.y);
         t2 = norm_inv.y * (self.m2.y - ori.y);
-        tmin = tmin.max(t1.min(t2));
-        tmax = tmax.min(t2.max(t1));
+        tnear = tnear.max(t1.min(t2));
+        tfar = tfar.min(t2.max(t1));
         
         //z
         t1 = norm_inv.z * (self.m1.z - ori.z);
         t2 = norm_inv.z * (self.m2.z - ori.z);
-        tmin = tmin.max(t1.min(t2));
-        tmax = tmax.min(t2.max(t1));
+        tnear = tnear.max(t1.min(t2));
+        tfar = tfar.min(t2.max(t1));
 
-        return tmin <= tmax;
+        return if tnear <= tfar { Some(tnear) } else { None };
     }
 }
 
@@ -337,36 +268,89 @@ pub(crate) struct PointLight {
 }
 
 pub struct World {
-    pub(crate) triangles: Vec<Triangle>,
+    pub(crate) shapes: Vec<Box<dyn Shape>>,
+    // the order every shape was added in, the bvh reorders them
+    pub(crate) shape_ids: Vec<usize>,
     pub(crate) bbox: Vec<BoundingBox>,
     textures: HashMap<usize, Texture>,
     texture_count: usize,
+    pub(crate) materials: Vec<Material>,
     pub(crate) point_lights: Vec<PointLight>,
+    pub(crate) lights: Vec<Light>,
+    pub(crate) environment: Option<Environment>,
+    // shadow rays per area light and to the environment at every shaded
+    // point
+    pub(crate) light_samples: usize,
+    bvh_settings: BvhSettings,
+    bvh_report: BvhReport,
     pub(crate) validated: bool
 }
 
+impl Default for World {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
 impl World {
     const INV255: f64 = 1.0 / 255.0;
     
     pub fn new() -> Self {
         return Self {
-            triangles: Vec::<Triangle>::new(),
+            shapes: Vec::<Box<dyn Shape>>::new(),
+            shape_ids: Vec::<usize>::new(),
             bbox: Vec::<BoundingBox>::new(),
             textures: HashMap::<usize, Texture>::new(),
             texture_count: 0,
+            materials: Vec::<Material>::new(),
             point_lights: Vec::<PointLight>::new(),
-            validated: true
+            lights: Vec::<Light>::new(),
+            environment: None,
+            light_samples: 16,
+            bvh_settings: BvhSettings::new(),
+            bvh_report: BvhReport::default(),
+            validated: false
         }
     }
 
+    //takes effect on the next compute()
+    pub fn set_bvh_settings(&mut self, settings: BvhSettings) {
+        self.bvh_settings = settings;
+        self.validated = false;
+    }
+
+    pub fn bvh_report(&self) -> &BvhReport {
+        return &self.bvh_report;
+    }
+
     pub fn create_point_light(&mut self, pos: Vector3, color: Color, lumen: f64) {
         self.point_lights.push(PointLight {
             pos, color, lumen
         });
+        self.validated = false;
+    }
+
+    pub fn create_light(&mut self, light: Light) {
+        self.lights.push(light);
+        self.validated = false;
+    }
+
+    pub fn set_environment(&mut self, environment: Option<Environment>) {
+        self.environment = environment;
+        self.validated = false;
     }
 
-    pub fn create_texture(&mut self, tex: &str) -> isize {
-        let img = image::open(tex).unwrap().to_rgb8();
+    //the path tracer always uses one, it averages over many paths anyway
+    pub fn set_light_samples(&mut self, samples: usize) {
+        self.light_samples = samples.max(1);
+    }
+
+    pub fn create_texture<P: AsRef<Path>>(&mut self, tex: P) -> Result<isize> {
+        let tex = tex.as_ref();
+        let img = match image::open(tex) {
+            Ok(img) => img.to_rgba8(),
+            Err(source) => return Err(Error::Image { path: tex.display().to_string(), source })
+        };
         let width = img.width();
         let height = img.height();
         
@@ -376,38 +360,72 @@ impl World {
 
         let id = self.texture_count;
         self.texture_count += 1;
-        return id as isize;
+        return Ok(id as isize);
     }
 
+    //shapes that still use it make the next compute() fail
     pub fn remove_texture(&mut self, id: usize) {
         self.textures.remove(&id);
+        self.validated = false;
+    }
+
+    //returns the id shapes use to refer to the material
+    pub fn create_material(&mut self, material: Material) -> isize {
+        self.materials.push(material);
+        self.validated = false;
+        return self.materials.len() as isize - 1;
     }
     
     pub fn create_triangle(&mut self,
                            p1: Vector3, p2: Vector3, p3: Vector3,
                            uv1: Vector2, uv2: Vector2, uv3: Vector2,
                            c1: Color, c2: Color, c3: Color,
-                           reflect: [f64; 3], tex: isize) {
-        let midvec = Vector3::new(
-                0.5 * (f64::max(p1.x, f64::max(p2.x, p3.x)) +
-                       f64::min(p1.x, f64::min(p2.x, p3.x))),
-                0.5 * (f64::max(p1.y, f64::max(p2.y, p3.y)) +
-                       f64::min(p1.y, f64::min(p2.y, p3.y))),
-                0.5 * (f64::max(p1.z, f64::max(p2.z, p3.z)) +
-                       f64::min(p1.z, f64::min(p2.z, p3.z))));
-        
+                           reflect: [f64; 3], tex: isize, mat: isize) {
+        self.push_triangle(p1, p2, p3, None, uv1, uv2, uv3, c1, c2, c3, reflect, tex, mat);
+    }
+
+    //triangle shaded with interpolated vertex normals
+    pub fn create_smooth_triangle(&mut self,
+                                  p1: Vector3, p2: Vector3, p3: Vector3,
+                                  n1: Vector3, n2: Vector3, n3: Vector3,
+                                  uv1: Vector2, uv2: Vector2, uv3: Vector2,
+                                  c1: Color, c2: Color, c3: Color,
+                                  reflect: [f64; 3], tex: isize, mat: isize) {
+        self.push_triangle(p1, p2, p3, Some([n1, n2, n3]),
+                           uv1, uv2, uv3, c1, c2, c3, reflect, tex, mat);
+    }
+
+    fn push_triangle(&mut self,
+                     p1: Vector3, p2: Vector3, p3: Vector3,
+                     normals: Option<[Vector3; 3]>,
+                     uv1: Vector2, uv2: Vector2, uv3: Vector2,
+                     c1: Color, c2: Color, c3: Color,
+                     reflect: [f64; 3], tex: isize, mat: isize) {
         let e1 = p2.sub(&p1);
         let e2 = p3.sub(&p1);
         let mut norm = e1.cross(&e2);
         norm.normalize_dis();
-        let norm_opp = Vector3::new(-norm.x, -norm.y, -norm.z);
+        let [n1, n2, n3] = normals.unwrap_or([norm; 3]);
         
-        self.triangles.push(Triangle {
+        self.add_shape(Triangle {
             p1, p2, p3, e1, e2, uv1, uv2, uv3, c1, c2, c3,
-            mid: midvec.x + midvec.y + midvec.z,
-            norm, norm_opp, reflect, tex
+            norm, n1, n2, n3, reflect, tex, mat
         });
-        
+    }
+
+    //textures wrap around the sphere with u along the equator and v from
+    //the bottom (0) to the top (1)
+    pub fn create_sphere(&mut self, center: Vector3, radius: f64,
+                         color: Color, reflect: f64, tex: isize, mat: isize) {
+        self.add_shape(Sphere {
+            center, radius, color, reflect, tex, mat
+        });
+    }
+
+    //adds any other kind of shape, see shape::Shape
+    pub fn add_shape<S: Shape + 'static>(&mut self, shape: S) {
+        self.shape_ids.push(self.shapes.len());
+        self.shapes.push(Box::new(shape));
         self.validated = false;
     }
 
@@ -415,100 +433,251 @@ impl World {
                         p1: Vector3, p2: Vector3, p3: Vector3, p4: Vector3,
                         uv1: Vector2, uv2: Vector2, uv3: Vector2, uv4: Vector2,
                         c1: Color, c2: Color, c3: Color, c4: Color, 
-                        reflect: [f64; 4], tex: isize) {
+                        reflect: [f64; 4], tex: isize, mat: isize) {
         self.create_triangle(p1, p2, p4, uv1, uv2, uv4, c1, c2, c4,
-                             [reflect[0], reflect[1], reflect[2]], tex);
+                             [reflect[0], reflect[1], reflect[2]], tex, mat);
         self.create_triangle(p1, p3, p4, uv1, uv3, uv4, c1, c3, c4,
-                             [reflect[0], reflect[2], reflect[3]], tex);
+                             [reflect[0], reflect[2], reflect[3]], tex, mat);
     }
 
-    pub fn compute(&mut self) {
-        self.triangles.sort_by(|a, b| a.mid.partial_cmp(&b.mid).unwrap());
+    pub fn compute(&mut self) -> Result<()> {
+        if self.shapes.is_empty() && self.lights.is_empty() && self.environment.is_none() {
+            return Err(Error::EmptyWorld);
+        }
 
-        self.bbox = Vec::<BoundingBox>::with_capacity(2 * self.triangles.len() - 1);
+        for (i, shape) in self.shapes.iter().enumerate() {
+            if !shape.is_valid() {
+                return Err(Error::InvalidShape(self.shape_ids[i]));
+            }
 
-        //initialise the first boxes
-        let mut left = 0;
-        for triangle in self.triangles.iter() {
-            self.bbox.push(BoundingBox {
-                m1: triangle.min_box(),
-                m2: triangle.max_box(),
-                left: -1,
-                right: -1
-            })
+            let tex = shape.texture();
+            if tex != -1 && !self.textures.contains_key(&(tex as usize)) {
+                return Err(Error::UnknownTexture(tex));
+            }
+
+            let mat = shape.material();
+            if mat != -1 && (mat < 0 || mat as usize >= self.materials.len()) {
+                return Err(Error::UnknownMaterial(mat));
+            }
         }
 
-        //loop until there is only one singular bounding box
-        let mut boxes = self.triangles.len() >> 1;
-        let mut start = 0;
-        while boxes >= 1 {
-            let mut i = 0;
-            
-            while i < boxes {
-                //combine bounding box
-                let mut j = start + i;
-
-                let lbox = &self.bbox[j];
-                let rbox = &self.bbox[j + 1];
-                
-                self.bbox.push(BoundingBox {
-                    m1: Vector3::new(
-                        lbox.m1.x.min(rbox.m1.x),
-                        lbox.m1.y.min(rbox.m1.y),
-                        lbox.m1.z.min(rbox.m1.z)),
-                    m2: Vector3::new(
-                        lbox.m2.x.max(rbox.m2.x),
-                        lbox.m2.y.max(rbox.m2.y),
-                        lbox.m2.z.max(rbox.m2.z)),
-                    left: j as isize,
-                    right: j as isize + 1
-                });
-                
-                i += 2;
+        for (i, material) in self.materials.iter().enumerate() {
+            if !material.is_valid() {
+                return Err(Error::InvalidMaterial(i));
             }
+        }
 
-            start += i;
-            
-            boxes = (self.bbox.len() - start) >> 1;
+        for (i, light) in self.lights.iter().enumerate() {
+            if !light.is_valid() {
+                return Err(Error::InvalidLight(i));
+            }
         }
 
+        let (bbox, order, report) = bvh::build(&mut self.shapes, &self.bvh_settings);
+        self.shape_ids = order.iter().map(|&i| self.shape_ids[i]).collect();
+        self.bbox = bbox;
+        self.bvh_report = report;
+
         self.validated = true;
         //println!("{:?}", self.bbox);
+        return Ok(());
     }
 
-    pub fn obtain_color(&self, tri_id: usize,
+    pub fn obtain_color(&self, shape_id: usize,
                         u: f64, v: f64) -> Color {
-        if self.point_lights.len() == 0 {
+        if self.point_lights.is_empty() && self.lights.is_empty() && self.environment.is_none() {
             return Color::new_zero();
         }
-        
-        let triangle = &self.triangles[tri_id];
-        let mut color = triangle.obtain_color(u, v);
-
-        if triangle.tex != -1 {
-            let uv = triangle.obtain_uv(u, v);
-            let tex = &self.textures[&(triangle.tex as usize)];
-            
-            let x = uv.x * (tex.width as f64);
-            let y = (1.0 - uv.y) * (tex.height as f64);
-            
-            let pix = tex.img.get_pixel(
-                (x as u32) % tex.width,
-                (y as u32) % tex.height);
-            
-            color.mul_self(
-                pix.0[0] as f64 * Self::INV255,
-                pix.0[1] as f64 * Self::INV255,
-                pix.0[2] as f64 * Self::INV255,
-                1.0);
+
+        return self.obtain_albedo(shape_id, u, v);
+    }
+
+    // vertex color times texture, whether or not anything lights it
+    pub(crate) fn obtain_albedo(&self, shape_id: usize, u: f64, v: f64) -> Color {
+        let mut color = self.shapes[shape_id].color(u, v);
+        if let Some(pix) = self.obtain_texel(shape_id, u, v) {
+            color.mul_self(pix[0], pix[1], pix[2], pix[3]);
         }
 
         return color;
     }
+
+    // how much of what is behind the surface it hides, from the vertex
+    // alpha times the texture alpha
+    pub(crate) fn obtain_opacity(&self, shape_id: usize, u: f64, v: f64) -> f64 {
+        let alpha = self.shapes[shape_id].color(u, v).a;
+        return match self.obtain_texel(shape_id, u, v) {
+            Some(pix) => alpha * pix[3],
+            None => alpha
+        }.clamp(0.0, 1.0);
+    }
+
+    //rgba of the texture at the hit, none without a texture
+    fn obtain_texel(&self, shape_id: usize, u: f64, v: f64) -> Option<[f64; 4]> {
+        let shape = &self.shapes[shape_id];
+        if shape.texture() == -1 {
+            return None;
+        }
+
+        //compute() rejects missing textures, this only guards against
+        //a world that is used without it
+        let tex = self.textures.get(&(shape.texture() as usize))?;
+        let uv = shape.uv(u, v);
+        
+        let x = uv.x * (tex.width as f64);
+        let y = (1.0 - uv.y) * (tex.height as f64);
+        
+        let pix = tex.img.get_pixel(
+            (x as u32) % tex.width,
+            (y as u32) % tex.height);
+        
+        return Some([
+            pix.0[0] as f64 * Self::INV255,
+            pix.0[1] as f64 * Self::INV255,
+            pix.0[2] as f64 * Self::INV255,
+            pix.0[3] as f64 * Self::INV255]);
+    }
+}
+
+// a file in the temp directory that no other test run writes to
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    return std::env::temp_dir().join(format!("fksray-{}-{name}", std::process::id()));
 }
 
-pub struct Camera {
-    pub pos: Vector3,
-    pub rot: Vector3,
-    pub fov: f64
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use image::Rgba;
+
+    use super::*;
+    use crate::fksray::material::Material;
+
+    fn white() -> Color {
+        return Color::new(1.0, 1.0, 1.0, 1.0);
+    }
+
+    fn sphere_world(tex: isize, mat: isize) -> World {
+        let mut world = World::new();
+        world.create_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, white(), 0.0, tex, mat);
+        return world;
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn sphere_hits() {
+        let world = sphere_world(-1, -1);
+        let sphere = &world.shapes[0];
+        let ori = Vector3::new(-5.0, 0.0, 0.0);
+
+        //the direction does not have to be unit length
+        let (t, u, v) = sphere.intersect(&ori, &Vector3::new(2.0, 0.0, 0.0)).unwrap();
+        assert_close(t, 2.0);
+        let hit = sphere.interaction(u, v);
+        assert_close(hit.pos.x, -1.0);
+        assert_close(hit.norm.x, -1.0);
+
+        assert!(sphere.intersect(&Vector3::new(-5.0, 1.5, 0.0), &Vector3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(sphere.intersect(&ori, &Vector3::new(-1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn sphere_hits_from_inside() {
+        let world = sphere_world(-1, -1);
+        let sphere = &world.shapes[0];
+
+        let (t, _, v) = sphere.intersect(&Vector3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 1.0, 0.0)).unwrap();
+        assert_close(t, 1.0);
+        assert_close(v, 1.0);
+
+        //the far side, and the normal still points out
+        let (t, u, v) = sphere.intersect(&Vector3::new(0.0, 0.0, 0.5), &Vector3::new(0.0, 0.0, -1.0)).unwrap();
+        assert_close(t, 1.5);
+        let hit = sphere.interaction(u, v);
+        assert_close(hit.pos.z, -1.0);
+        assert_close(hit.norm.z, -1.0);
+
+        //a ray that starts on the surface does not hit it again
+        let (t, _, _) = sphere.intersect(&Vector3::new(1.0, 0.0, 0.0), &Vector3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert_close(t, 2.0);
+    }
+
+    #[test]
+    fn sphere_uv() {
+        let world = sphere_world(-1, -1);
+        let sphere = &world.shapes[0];
+        let center = Vector3::new(0.0, 0.0, 0.0);
+
+        for (dir, u, v) in [((1.0, 0.0, 0.0), 0.5, 0.5), ((0.0, 0.0, 1.0), 0.75, 0.5),
+                            ((0.0, 0.0, -1.0), 0.25, 0.5), ((0.0, 1.0, 0.0), 0.5, 1.0),
+                            ((0.0, -1.0, 0.0), 0.5, 0.0)] {
+            let dir = Vector3::new(dir.0, dir.1, dir.2);
+            let hit = sphere.intersect(&center, &dir).unwrap();
+            //longitude is meaningless at the poles
+            if v == 0.5 {
+                assert_close(hit.1, u);
+            }
+            assert_close(hit.2, v);
+        }
+
+        //interaction goes back to the point intersect found the uv at
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.7, 0.9), (0.95, 0.4)] {
+            let hit = sphere.interaction(u, v);
+            assert_close(hit.norm.dot(&hit.norm), 1.0);
+            let found = sphere.intersect(&center, &hit.pos).unwrap();
+            assert_close(found.1, u);
+            assert_close(found.2, v);
+            let uv = sphere.uv(u, v);
+            assert_eq!((uv.x, uv.y), (u, v));
+        }
+    }
+
+    #[test]
+    fn color_strings() {
+        let color = Color::new_string("#FF8000").unwrap();
+        assert_eq!([color.r, color.g, color.b, color.a], [1.0, 128.0 / 255.0, 0.0, 1.0]);
+        let color = Color::new_string("#00000080").unwrap();
+        assert_eq!(color.a, 128.0 / 255.0);
+
+        for s in ["", "#", "FF8000", "#FF800", "#FF80000", "#FF8000800", "#GG8000",
+                  "#FF80 0", "#+F8000", "#FF8000é", "#éé8000"] {
+            assert!(matches!(Color::new_string(s), Err(Error::InvalidColor(_))), "{s}");
+        }
+    }
+
+    #[test]
+    fn unknown_ids() {
+        assert!(matches!(sphere_world(0, -1).compute(), Err(Error::UnknownTexture(0))));
+        assert!(matches!(sphere_world(-1, 0).compute(), Err(Error::UnknownMaterial(0))));
+        assert!(matches!(sphere_world(-1, -2).compute(), Err(Error::UnknownMaterial(-2))));
+
+        let mut world = sphere_world(-1, 0);
+        assert_eq!(world.create_material(Material::new_diffuse(white())), 0);
+        assert!(world.compute().is_ok());
+    }
+
+    #[test]
+    fn removed_texture() {
+        let path = temp_path("texture.png");
+        RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255])).save(&path).unwrap();
+        let mut world = World::new();
+        let tex = world.create_texture(&path);
+        fs::remove_file(&path).unwrap();
+        let tex = tex.unwrap();
+
+        world.create_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0, white(), 0.0, tex, -1);
+        world.create_point_light(Vector3::new(0.0, 5.0, 0.0), white(), 1.0);
+        world.compute().unwrap();
+        assert_eq!(world.obtain_color(0, 0.5, 0.5).g, 0.0);
+
+        world.remove_texture(tex as usize);
+        assert!(!world.validated);
+        assert!(matches!(world.compute(), Err(Error::UnknownTexture(_))));
+        //a world used without compute() falls back to the shape color
+        assert_eq!(world.obtain_color(0, 0.5, 0.5).g, 1.0);
+    }
+}
@@ -0,0 +1,3 @@
+#![allow(clippy::needless_return, clippy::too_many_arguments)]
+
+pub mod fksray;
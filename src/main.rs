@@ -1,152 +1,456 @@
-mod fksray;
+#![allow(clippy::needless_return)]
 
-use fksray::util::{Color, Vector3, Vector2, Camera, World};
-use image::RgbImage;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use fksraytracer::fksray::{self, Crop, RenderSettings};
+use fksraytracer::fksray::bvh::BvhSettings;
+use fksraytracer::fksray::camera::{Camera, Projection};
+use fksraytracer::fksray::film::{self, Film, ToneMap};
+use fksraytracer::fksray::output;
+use fksraytracer::fksray::pass::Pass;
+use fksraytracer::fksray::stats::Progress;
+use fksraytracer::fksray::filter::{Filter, Sampling};
+use fksraytracer::fksray::integrator::Integrator;
+use fksraytracer::fksray::util::{Vector3, World};
+
+const USAGE: &str = "\
+usage: fksraytracer <scene.json> [options]
+
+options:
+  -o, --output <file>      output image, .exr and .pfm keep the linear values (default output.png)
+  -W, --width <px>         image width (default 512)
+  -H, --height <px>        image height (default 512)
+      --crop <x,y,w,h>     only render this part of the image
+      --camera-pos <x,y,z> override the scene camera position
+      --camera-rot <x,y,z> override the scene camera rotation in degrees
+      --look-at <x,y,z>    point the camera at this position instead
+      --camera-up <x,y,z>  up direction used with --look-at (default 0,1,0)
+      --fov <deg>          override the scene camera vertical field of view
+      --aperture <r>       lens radius for depth of field, 0 is a pinhole
+      --focus-distance <d> distance of the plane in focus
+      --projection <name>  perspective, orthographic, fisheye or equirectangular
+      --ortho-height <h>   world height the orthographic view covers
+  -s, --samples <n>        rays per pixel (default 1)
+      --progressive <n>    take the samples in rounds of n per pixel, saving the image
+                           after every round
+      --checkpoint <file>  save every sample taken so far here after each round
+      --resume             carry on from the --checkpoint file if it exists, it must
+                           have been made with the same size, crop, sampling and passes
+      --sampling <mode>    stratified or jittered sample positions (default stratified)
+      --filter <name>      box, tent, gaussian or mitchell (default box)
+      --seed <n>           seed for the sample positions (default 0)
+      --integrator <name>  legacy or path (default legacy)
+  -d, --depth <n>          maximum reflection bounces (default 5)
+      --light-samples <n>  shadow rays per area light of the legacy integrator (default 16)
+      --tone-map <name>    clamp, reinhard or aces (default clamp)
+      --exposure <stops>   brighten or darken before tone mapping (default 0)
+      --no-srgb            write linear values instead of srgb encoding them
+      --passes <a,b,..>    extra images: depth, normal, albedo, uv, shape_id, texture_id,
+                           direct, reflection, alpha or all. they are layers of an .exr
+                           output, other formats get a <name>.<pass>.exr per pass
+  -t, --threads <n>        worker threads, 0 uses every core (default 0)
+      --leaf-size <n>      maximum shapes per bvh leaf (default 4)
+  -q, --quiet              do not print the progress and the statistics
+  -h, --help               print this message";
+
+struct Options {
+    scene: PathBuf,
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    crop: Option<Crop>,
+    camera_pos: Option<Vector3>,
+    camera_rot: Option<Vector3>,
+    look_at: Option<Vector3>,
+    camera_up: Option<Vector3>,
+    fov: Option<f64>,
+    aperture: Option<f64>,
+    focus_distance: Option<f64>,
+    projection: Option<Projection>,
+    samples: usize,
+    progressive: Option<usize>,
+    checkpoint: Option<PathBuf>,
+    resume: bool,
+    sampling: Sampling,
+    filter: Filter,
+    seed: u64,
+    integrator: Integrator,
+    depth: usize,
+    light_samples: Option<usize>,
+    tone_map: ToneMap,
+    exposure: f64,
+    srgb: bool,
+    passes: Vec<Pass>,
+    threads: usize,
+    quiet: bool,
+    leaf_size: Option<usize>
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    return value.parse::<T>().map_err(|_| format!("invalid value \"{value}\" for {name}"));
+}
+
+fn parse_list<T: FromStr>(name: &str, value: &str, len: usize) -> Result<Vec<T>, String> {
+    let list = value.split(',')
+        .map(|v| parse_value::<T>(name, v.trim()))
+        .collect::<Result<Vec<T>, String>>()?;
+
+    if list.len() != len {
+        return Err(format!("{name} takes {len} comma separated values"));
+    }
+
+    return Ok(list);
+}
+
+fn parse_vector(name: &str, value: &str) -> Result<Vector3, String> {
+    let v = parse_list::<f64>(name, value, 3)?;
+    return Ok(Vector3::new(v[0], v[1], v[2]));
+}
+
+fn parse_passes(value: &str) -> Result<Vec<Pass>, String> {
+    if value == "all" {
+        return Ok(Pass::ALL.to_vec());
+    }
+
+    let mut passes = Vec::<Pass>::new();
+    for name in value.split(',') {
+        let pass = name.trim().parse::<Pass>()?;
+        if !passes.contains(&pass) {
+            passes.push(pass);
+        }
+    }
+
+    return Ok(passes);
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut scene = None;
+    let mut projection = None;
+    let mut ortho_height = None;
+    let mut options = Options {
+        scene: PathBuf::new(),
+        output: PathBuf::from("output.png"),
+        width: 512,
+        height: 512,
+        crop: None,
+        camera_pos: None,
+        camera_rot: None,
+        look_at: None,
+        camera_up: None,
+        fov: None,
+        aperture: None,
+        focus_distance: None,
+        projection: None,
+        samples: 1,
+        progressive: None,
+        checkpoint: None,
+        resume: false,
+        sampling: Sampling::Stratified,
+        filter: Filter::new_box(),
+        seed: 0,
+        integrator: Integrator::Legacy,
+        depth: 5,
+        light_samples: None,
+        tone_map: ToneMap::Clamp,
+        exposure: 0.0,
+        srgb: true,
+        passes: Vec::<Pass>::new(),
+        threads: 0,
+        quiet: false,
+        leaf_size: None
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{USAGE}");
+            process::exit(0);
+        }
+
+        if arg == "--no-srgb" {
+            options.srgb = false;
+            continue;
+        }
+
+        if arg == "--resume" {
+            options.resume = true;
+            continue;
+        }
+
+        if arg == "-q" || arg == "--quiet" {
+            options.quiet = true;
+            continue;
+        }
+
+        if !arg.starts_with('-') {
+            if scene.is_some() {
+                return Err(format!("unexpected argument \"{arg}\""));
+            }
+            scene = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "-o" | "--output" => options.output = PathBuf::from(value),
+            "-W" | "--width" => options.width = parse_value(&arg, &value)?,
+            "-H" | "--height" => options.height = parse_value(&arg, &value)?,
+            "--crop" => {
+                let c = parse_list::<u32>(&arg, &value, 4)?;
+                options.crop = Some(Crop::new(c[0], c[1], c[2], c[3]));
+            },
+            "--camera-pos" => options.camera_pos = Some(parse_vector(&arg, &value)?),
+            "--camera-rot" => options.camera_rot = Some(parse_vector(&arg, &value)?),
+            "--look-at" => options.look_at = Some(parse_vector(&arg, &value)?),
+            "--camera-up" => options.camera_up = Some(parse_vector(&arg, &value)?),
+            "--fov" => options.fov = Some(parse_value(&arg, &value)?),
+            "--aperture" => options.aperture = Some(parse_value(&arg, &value)?),
+            "--focus-distance" => options.focus_distance = Some(parse_value(&arg, &value)?),
+            "--projection" => projection = Some(value),
+            "--ortho-height" => ortho_height = Some(parse_value(&arg, &value)?),
+            "-s" | "--samples" => options.samples = parse_value(&arg, &value)?,
+            "--progressive" => options.progressive = Some(parse_value(&arg, &value)?),
+            "--checkpoint" => options.checkpoint = Some(PathBuf::from(value)),
+            "--sampling" => options.sampling = value.parse()?,
+            "--filter" => options.filter = value.parse()?,
+            "--seed" => options.seed = parse_value(&arg, &value)?,
+            "--integrator" => options.integrator = value.parse()?,
+            "-d" | "--depth" => options.depth = parse_value(&arg, &value)?,
+            "--light-samples" => options.light_samples = Some(parse_value(&arg, &value)?),
+            "--tone-map" => options.tone_map = value.parse()?,
+            "--exposure" => options.exposure = parse_value(&arg, &value)?,
+            "--passes" => options.passes = parse_passes(&value)?,
+            "-t" | "--threads" => options.threads = parse_value(&arg, &value)?,
+            "--leaf-size" => options.leaf_size = Some(parse_value(&arg, &value)?),
+            _ => return Err(format!("unknown option \"{arg}\""))
+        }
+    }
+
+    options.scene = scene.ok_or("no scene file given")?;
+
+    if options.camera_rot.is_some() && options.look_at.is_some() {
+        return Err("--camera-rot and --look-at can not be used together".to_string());
+    }
+
+    if options.aperture.is_some_and(|aperture| aperture < 0.0) {
+        return Err("the aperture can not be negative".to_string());
+    }
+
+    if options.focus_distance.is_some_and(|distance| distance <= 0.0) {
+        return Err("the focus distance must be positive".to_string());
+    }
+
+    if let Some(projection) = projection {
+        options.projection = Some(Projection::from_name(&projection, ortho_height)
+            .map_err(|err| err.to_string())?);
+    } else if ortho_height.is_some() {
+        return Err("--ortho-height needs --projection orthographic".to_string());
+    }
+
+    if options.width == 0 || options.height == 0 {
+        return Err("the image size must not be zero".to_string());
+    }
+
+    film::pixel_count(options.width, options.height).map_err(|err| err.to_string())?;
+
+    let float_output = options.output.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("exr") || e.eq_ignore_ascii_case("pfm"));
+    if !float_output && image::ImageFormat::from_path(&options.output).is_err() {
+        return Err(format!("unknown image format for \"{}\"", options.output.display()));
+    }
+
+    if options.samples == 0 {
+        return Err("at least one sample per pixel is needed".to_string());
+    }
+
+    if options.progressive == Some(0) {
+        return Err("a progressive round needs at least one sample".to_string());
+    }
+
+    if options.resume && options.checkpoint.is_none() {
+        return Err("--resume needs a --checkpoint file".to_string());
+    }
+
+    if !options.exposure.is_finite() {
+        return Err("the exposure must be a finite number".to_string());
+    }
+
+    if options.light_samples == Some(0) {
+        return Err("at least one light sample is needed".to_string());
+    }
+
+    return Ok(options);
+}
+
+fn format_duration(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        return format!("{}h {:02}m {:02}s", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    }
+    if seconds >= 60 {
+        return format!("{}m {:02}s", seconds / 60, seconds % 60);
+    }
+
+    return format!("{seconds}s");
+}
+
+// one line on stderr that is written over, at most a few times a second
+fn print_progress(progress: &Progress, last: &mut Option<Instant>) {
+    let finished = progress.done >= progress.total;
+    if last.is_some_and(|last| last.elapsed().as_millis() < 250) && !finished {
+        return;
+    }
+    *last = Some(Instant::now());
+
+    let eta = progress.eta().map_or("-".to_string(), format_duration);
+    let round = if progress.rounds > 1 {
+        format!(", round {}/{}", progress.round, progress.rounds)
+    } else {
+        String::new()
+    };
+    eprint!("\r{:5.1}%{round}, eta {eta}, {:.2}M rays/s   ",
+            progress.percent(), progress.rays_per_second() / 1e6);
+    if finished {
+        eprintln!();
+    }
+}
+
+// the camera of the scene with the camera options on top
+fn apply_camera_options(options: &Options, mut camera: Camera) -> fksray::error::Result<Camera> {
+    if let Some(pos) = options.camera_pos {
+        camera.pos = pos;
+    }
+    if let Some(fov) = options.fov {
+        camera.fov = fov;
+    }
+    let (aperture, focus_distance) = (camera.aperture, camera.focus_distance);
+    let projection = camera.projection;
+    if let Some(rot) = options.camera_rot {
+        camera = Camera::from_euler(camera.pos, rot, camera.fov);
+        camera.set_lens(aperture, focus_distance);
+        camera.projection = projection;
+    }
+    if options.look_at.is_some() || options.camera_up.is_some() {
+        //keep looking the same way when only the up direction changes
+        let target = options.look_at.unwrap_or(camera.pos.add(&camera.forward()));
+        let up = options.camera_up.unwrap_or(Vector3::new(0.0, 1.0, 0.0));
+        camera = Camera::look_at(camera.pos, target, up, camera.fov)?;
+        camera.projection = projection;
+        //a new target is also what should be in focus
+        if options.look_at.is_none() {
+            camera.set_lens(aperture, focus_distance);
+        } else {
+            camera.aperture = aperture;
+        }
+    }
+    if let Some(aperture) = options.aperture {
+        camera.aperture = aperture;
+    }
+    if let Some(focus_distance) = options.focus_distance {
+        camera.focus_distance = focus_distance;
+    }
+    if let Some(projection) = options.projection {
+        camera.projection = projection;
+    }
+
+    camera.validate()?;
+    return Ok(camera);
+}
 
 fn main() {
-    let mut world = World::new();
-    let id = world.create_texture("test.jpg");
-    world.create_plane(
-        Vector3::new(-100.0, -70.0, -100.0),
-        Vector3::new(100.0, -70.0, -100.0),
-        Vector3::new(-100.0, -70.0, 200.0),
-        Vector3::new(100.0, -70.0, 200.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#CFAB23FF"),
-        Color::new_string("#FF0000"),
-        Color::new_string("#00FF00"),
-        Color::new_string("#0000FFFF"),
-        [0.0, 0.0, 0.0, 0.0],
-        id
-    );
-    world.create_plane(
-        Vector3::new(-100.0, 70.0, -100.0),
-        Vector3::new(100.0, 70.0, -100.0),
-        Vector3::new(-100.0, 70.0, 200.0),
-        Vector3::new(100.0, 70.0, 200.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#CFAB23FF"),
-        Color::new_string("#FF0000"),
-        Color::new_string("#00FF00"),
-        Color::new_string("#0000FFFF"),
-        [0.0, 0.0, 0.0, 0.0],
-        id
-    );
-    world.create_plane(
-        Vector3::new(-70.0, -100.0, -100.0),
-        Vector3::new(-70.0, 100.0, -100.0),
-        Vector3::new(-70.0, -100.0, 200.0),
-        Vector3::new(-70.0, 100.0, 200.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#485783"),
-        Color::new_string("#102939"),
-        Color::new_string("#FFFFFF"),
-        Color::new_string("#DFFFFD"),
-        [0.0, 0.0, 0.0, 0.0],
-        id
-    );
-    world.create_plane(
-        Vector3::new(70.0, -100.0, -100.0),
-        Vector3::new(70.0, 100.0, -100.0),
-        Vector3::new(70.0, -100.0, 200.0),
-        Vector3::new(70.0, 100.0, 200.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#485783"),
-        Color::new_string("#102939"),
-        Color::new_string("#FFFFFF"),
-        Color::new_string("#DFFFFD"),
-        [0.0, 0.0, 0.0, 0.0],
-        id
-    );
-    world.create_plane(
-        Vector3::new(-100.0, -100.0, 200.0),
-        Vector3::new(100.0, -100.0, 200.0),
-        Vector3::new(-100.0, 100.0, 200.0),
-        Vector3::new(100.0, 100.0, 200.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        [0.5, 0.5, 0.5, 0.5],
-        -1
-    );
-    world.create_plane(
-        Vector3::new(-100.0, -100.0, -10.0),
-        Vector3::new(100.0, -100.0, -10.0),
-        Vector3::new(-100.0, 100.0, -10.0),
-        Vector3::new(100.0, 100.0, -10.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#DFABC3"),
-        Color::new_string("#000000"),
-        Color::new_string("#BB3399"),
-        Color::new_string("#293883"),
-        [0.0, 0.0, 0.0, 0.0],
-        -1
-    );
-    world.create_plane(
-        Vector3::new(-30.0, -80.0, 110.0),
-        Vector3::new(30.0, -80.0, 110.0),
-        Vector3::new(-30.0, 0.0, 50.0),
-        Vector3::new(30.0, 0.0, 50.0),
-        Vector2::new(0.0, 0.0),
-        Vector2::new(1.0, 0.0),
-        Vector2::new(0.0, 1.0),
-        Vector2::new(1.0, 1.0),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        Color::new_string("#000000"),
-        [1.0, 1.0, 1.0, 1.0],
-        -1
-    );
-    world.create_point_light(
-        Vector3::new(0.0, 50.0, 100.0),
-        Color::new_string("#FF0000"),
-        15000.0
-    );
-    world.create_point_light(
-        Vector3::new(0.0, -40.0, 100.0),
-        Color::new_string("#00FF00"),
-        15000.0
-    );
-    world.create_point_light(
-        Vector3::new(30.0, 0.0, 0.0),
-        Color::new_string("#0000FF"),
-        15000.0
-    );
-    
-    world.compute();
-
-    let mut image = image::open("output.png").unwrap().to_rgb8();//RgbImage::new(512, 512);
-    
-    fksray::raytracer(
-        &mut image,
-        &Camera {
-            pos: Vector3::new(0.0, 60.0, 0.0),
-            rot: Vector3::new(0.0, -40.0, 0.0),
-            fov: 100.0,
-        },
-        &mut world
-    );
-
-    image.save("output.png").unwrap();
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    let timer = Instant::now();
+    let (mut world, camera, warnings) = match World::load_scene(&options.scene) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("error: could not load {}: {err}", options.scene.display());
+            process::exit(1);
+        }
+    };
+    for warning in warnings.iter() {
+        eprintln!("warning: {warning}");
+    }
+
+    let camera = match apply_camera_options(&options, camera) {
+        Ok(camera) => camera,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(leaf_size) = options.leaf_size {
+        let mut bvh_settings = BvhSettings::new();
+        bvh_settings.max_leaf_size = leaf_size;
+        world.set_bvh_settings(bvh_settings);
+    }
+
+    if let Some(light_samples) = options.light_samples {
+        world.set_light_samples(light_samples);
+    }
+
+    let mut settings = RenderSettings::new();
+    settings.crop = options.crop;
+    settings.threads = options.threads;
+    settings.samples = options.samples;
+    settings.progressive = options.progressive;
+    settings.checkpoint = options.checkpoint;
+    settings.resume = options.resume;
+    settings.sampling = options.sampling;
+    settings.filter = options.filter;
+    settings.seed = options.seed;
+    settings.integrator = options.integrator;
+    settings.max_depth = options.depth;
+    settings.tone.tone_map = options.tone_map;
+    settings.tone.exposure = options.exposure;
+    settings.tone.srgb = options.srgb;
+    settings.passes = options.passes;
+    settings.preview = Some(options.output.clone());
+
+    let load_time = timer.elapsed();
+
+    let mut film = match Film::new(options.width, options.height) {
+        Ok(film) => film,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    };
+    let mut last = None;
+    let mut observer = |progress: &Progress| {
+        if !options.quiet {
+            print_progress(progress, &mut last);
+        }
+    };
+    let mut stats = match fksray::raytracer_with_progress(
+        &mut film, &camera, &mut world, &settings, &mut observer) {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(1);
+        }
+    };
+
+    let timer = Instant::now();
+    if let Err(err) = output::save(&options.output, &film, &settings.tone) {
+        eprintln!("error: could not save {}: {err}", options.output.display());
+        process::exit(1);
+    }
+
+    stats.phases.insert(0, ("load".to_string(), load_time));
+    stats.add_phase("output", timer.elapsed());
+    if !options.quiet {
+        eprintln!("{}", world.bvh_report());
+        eprintln!("{stats}");
+    }
 }